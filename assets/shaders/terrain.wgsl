@@ -0,0 +1,151 @@
+#import bevy_pbr::mesh_view_bindings
+#import bevy_pbr::mesh_bindings
+#import bevy_pbr::pbr_types
+#import bevy_pbr::utils
+#import bevy_pbr::clustered_forward
+#import bevy_pbr::lighting
+#import bevy_pbr::pbr_ambient
+#import bevy_pbr::shadows
+#import bevy_pbr::fog
+#import bevy_pbr::pbr_functions
+
+#import bevy_pbr::mesh_functions
+
+struct TerrainMaterial {
+    tint: vec4<f32>,
+};
+
+@group(1) @binding(0)
+var<uniform> material: TerrainMaterial;
+
+struct Vertex {
+    @location(0) position: vec3<f32>,
+    @location(1) normal: vec3<f32>,
+    @location(2) color: vec4<f32>,
+    @location(3) material_id: u32,
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) world_position: vec4<f32>,
+    @location(1) world_normal: vec3<f32>,
+    @location(2) color: vec4<f32>,
+    @location(3) @interpolate(flat) material_id: u32,
+};
+
+@vertex
+fn vertex(vertex: Vertex) -> VertexOutput {
+    var out: VertexOutput;
+    out.world_position = mesh_position_local_to_world(mesh.model, vec4<f32>(vertex.position, 1.0));
+    out.clip_position = mesh_position_world_to_clip(out.world_position);
+    out.world_normal = mesh_normal_local_to_world(vertex.normal);
+    out.color = vertex.color;
+    out.material_id = vertex.material_id;
+    return out;
+}
+
+fn hash(p: vec2<f32>) -> f32 {
+    return fract(sin(dot(p, vec2<f32>(127.1, 311.7))) * 43758.5453);
+}
+
+fn value_noise(p: vec2<f32>) -> f32 {
+    let i = floor(p);
+    let f = fract(p);
+    let u = f * f * (3.0 - 2.0 * f);
+    let a = hash(i);
+    let b = hash(i + vec2<f32>(1.0, 0.0));
+    let c = hash(i + vec2<f32>(0.0, 1.0));
+    let d = hash(i + vec2<f32>(1.0, 1.0));
+    return mix(mix(a, b, u.x), mix(c, d, u.x), u.y);
+}
+
+// Layers match TerrainLayer: 0 is rock, 1 is sand and 2 is sediment
+
+// Detail of a layer projected along one axis, in 0..1
+fn layer_detail(layer: u32, p: vec2<f32>, height: f32) -> f32 {
+    switch layer {
+        case 0u: {
+            // Bands of strata, broken up a little
+            return 0.5 + 0.5 * sin(height * 1.7 + value_noise(p * 0.3) * 4.0);
+        }
+        case 1u: {
+            return value_noise(p * 6.0);
+        }
+        default: {
+            return value_noise(p * 0.8) * 0.5 + value_noise(p * 3.0) * 0.5;
+        }
+    }
+}
+
+fn detail_strength(layer: u32) -> f32 {
+    switch layer {
+        case 0u: {
+            return 0.35;
+        }
+        case 1u: {
+            return 0.15;
+        }
+        default: {
+            return 0.25;
+        }
+    }
+}
+
+fn layer_roughness(layer: u32) -> f32 {
+    switch layer {
+        case 0u: {
+            return 0.75;
+        }
+        case 1u: {
+            return 0.95;
+        }
+        default: {
+            return 0.9;
+        }
+    }
+}
+
+struct FragmentInput {
+    @builtin(front_facing) is_front: bool,
+    @builtin(position) frag_coord: vec4<f32>,
+    @location(0) world_position: vec4<f32>,
+    @location(1) world_normal: vec3<f32>,
+    @location(2) color: vec4<f32>,
+    @location(3) @interpolate(flat) material_id: u32,
+};
+
+@fragment
+fn fragment(in: FragmentInput) -> @location(0) vec4<f32> {
+    let position = in.world_position.xyz;
+    let normal = normalize(in.world_normal);
+
+    // Triplanar projection, each axis weighted by how much the surface faces it
+    var weights = pow(abs(normal), vec3<f32>(4.0));
+    weights = weights / (weights.x + weights.y + weights.z);
+    let layer = in.material_id;
+    let detail = layer_detail(layer, position.yz, position.y) * weights.x
+        + layer_detail(layer, position.xz, position.y) * weights.y
+        + layer_detail(layer, position.xy, position.y) * weights.z;
+    let shade = 1.0 + (detail - 0.5) * 2.0 * detail_strength(layer);
+
+    var pbr_input: PbrInput = pbr_input_new();
+    pbr_input.material.base_color = vec4<f32>(in.color.rgb * material.tint.rgb * shade, 1.0);
+    pbr_input.material.perceptual_roughness = layer_roughness(layer);
+    pbr_input.material.reflectance = 0.2;
+    pbr_input.frag_coord = in.frag_coord;
+    pbr_input.world_position = in.world_position;
+    pbr_input.world_normal = prepare_world_normal(in.world_normal, false, in.is_front);
+    pbr_input.is_orthographic = view.projection[3].w == 1.0;
+    pbr_input.N = pbr_input.world_normal;
+    pbr_input.V = calculate_view(in.world_position, pbr_input.is_orthographic);
+    pbr_input.flags = mesh.flags;
+
+    var output_color = pbr(pbr_input);
+    if (fog.mode != FOG_MODE_OFF) {
+        output_color = apply_fog(output_color, position, view.world_position.xyz);
+    }
+#ifdef TONEMAP_IN_SHADER
+    output_color = tone_mapping(output_color);
+#endif
+    return output_color;
+}
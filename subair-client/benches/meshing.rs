@@ -0,0 +1,238 @@
+//! Benchmarks of every stage of chunk meshing, run with `cargo bench`
+
+use bevy::prelude::*;
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use std::sync::Arc;
+use subair::kd_tree::KdTree;
+use subair::world::density::{Add, DensityFunction, HeightBias, NoiseLayer, NoiseSettings};
+use subair::world::generate::{
+    build_collider, deduplicate_vertices, generate_collider, generate_world, marching_cubes,
+    marching_cubes_indexed, NormalMode, Welding,
+};
+use subair::world::grid::SampleGrid;
+use subair::world::normals::calculate_normals;
+
+const CHUNK_SIZES: [usize; 3] = [16, 32, 64];
+
+/// Noisy terrain crossing every chunk size, so there is always a surface
+fn terrain() -> Add {
+    let settings = NoiseSettings {
+        octaves: 3,
+        ..default()
+    };
+    Add(vec![
+        Box::new(NoiseLayer::new(1, settings, 1.0)),
+        Box::new(HeightBias {
+            height: 8.0,
+            gradient: 0.05,
+        }),
+    ])
+}
+
+fn grid(density: &dyn DensityFunction, size: usize) -> SampleGrid {
+    SampleGrid::new(density, Vec3::ZERO, size + 1, 1, false)
+}
+
+/// Triangle corners of a chunk, before deduplication
+fn soup(density: &dyn DensityFunction, size: usize) -> Vec<Vec3> {
+    marching_cubes(&grid(density, size), 1).0
+}
+
+fn indexed_mesh(density: &dyn DensityFunction, size: usize) -> (Vec<[f32; 3]>, Vec<u32>) {
+    let (vertices, indices, _) = marching_cubes_indexed(&grid(density, size), 1);
+    (
+        vertices.into_iter().map(|v| v.to_array()).collect(),
+        indices,
+    )
+}
+
+fn sampling(c: &mut Criterion) {
+    let density = terrain();
+    let mut group = c.benchmark_group("sample_grid");
+    for size in CHUNK_SIZES {
+        group.bench_with_input(BenchmarkId::new("batch", size), &size, |b, &size| {
+            b.iter(|| grid(&density, size))
+        });
+        // Every point on its own, as marching cubes sampled before the grid
+        group.bench_with_input(BenchmarkId::new("single", size), &size, |b, &size| {
+            b.iter(|| {
+                let samples = size as i32 + 1;
+                let mut values = Vec::with_capacity((samples * samples * samples) as usize);
+                for x in 0..samples {
+                    for y in 0..samples {
+                        for z in 0..samples {
+                            values.push(density.sample(IVec3::new(x, y, z).as_vec3()));
+                        }
+                    }
+                }
+                values
+            })
+        });
+    }
+    group.finish();
+}
+
+/// The noise alone, which does most of the sampling work
+fn noise(c: &mut Criterion) {
+    let noise = NoiseLayer::new(
+        1,
+        NoiseSettings {
+            octaves: 3,
+            ..default()
+        },
+        1.0,
+    );
+    let mut group = c.benchmark_group("noise_layer");
+    for size in CHUNK_SIZES {
+        let samples = size as i32 + 1;
+        let points: Vec<_> = (0..samples * samples * samples)
+            .map(|i| IVec3::new(i / (samples * samples), i / samples % samples, i % samples))
+            .map(|p| p.as_vec3())
+            .collect();
+        let mut out = vec![0.0; points.len()];
+        group.bench_with_input(BenchmarkId::new("batch", size), &size, |b, _| {
+            b.iter(|| noise.sample_batch(&points, &mut out))
+        });
+        group.bench_with_input(BenchmarkId::new("single", size), &size, |b, _| {
+            b.iter(|| {
+                for (point, out) in points.iter().zip(out.iter_mut()) {
+                    *out = noise.sample(*point);
+                }
+            })
+        });
+    }
+    group.finish();
+}
+
+fn marching(c: &mut Criterion) {
+    let density = terrain();
+    let mut group = c.benchmark_group("marching_cubes");
+    for size in CHUNK_SIZES {
+        let grid = grid(&density, size);
+        group.bench_with_input(BenchmarkId::new("soup", size), &grid, |b, grid| {
+            b.iter(|| marching_cubes(grid, 1))
+        });
+        group.bench_with_input(BenchmarkId::new("indexed", size), &grid, |b, grid| {
+            b.iter(|| marching_cubes_indexed(grid, 1))
+        });
+    }
+    group.finish();
+}
+
+fn kd_tree(c: &mut Criterion) {
+    let density = terrain();
+    let mut group = c.benchmark_group("kd_tree");
+    for size in CHUNK_SIZES {
+        let points = soup(&density, size);
+        group.bench_with_input(BenchmarkId::new("construct", size), &points, |b, points| {
+            b.iter(|| KdTree::new(points.iter().copied().enumerate().map(|(i, p)| (p, i))))
+        });
+        let tree = KdTree::new(points.iter().copied().enumerate().map(|(i, p)| (p, i)));
+        group.bench_with_input(
+            BenchmarkId::new("within_radius", size),
+            &points,
+            |b, points| {
+                b.iter(|| {
+                    let mut found = 0;
+                    for point in points {
+                        tree.within_radius(*point, 1.0e-7, |_, _| found += 1);
+                    }
+                    found
+                })
+            },
+        );
+    }
+    group.finish();
+}
+
+fn deduplication(c: &mut Criterion) {
+    let density = terrain();
+    let mut group = c.benchmark_group("deduplicate_vertices");
+    for size in CHUNK_SIZES {
+        let points = soup(&density, size);
+        group.bench_with_input(BenchmarkId::from_parameter(size), &points, |b, points| {
+            b.iter(|| deduplicate_vertices(points.clone()))
+        });
+    }
+    group.finish();
+}
+
+fn normals(c: &mut Criterion) {
+    let density = terrain();
+    let mut group = c.benchmark_group("calculate_normals");
+    for size in CHUNK_SIZES {
+        let (vertices, indices) = indexed_mesh(&density, size);
+        group.bench_with_input(BenchmarkId::from_parameter(size), &size, |b, _| {
+            b.iter(|| calculate_normals(&vertices, &indices))
+        });
+    }
+    group.finish();
+}
+
+fn collider(c: &mut Criterion) {
+    let density = terrain();
+    let mut group = c.benchmark_group("collider");
+    for size in CHUNK_SIZES {
+        let (vertices, indices) = indexed_mesh(&density, size);
+        group.bench_with_input(BenchmarkId::from_parameter(size), &size, |b, _| {
+            b.iter(|| build_collider(&vertices, &indices))
+        });
+    }
+    group.finish();
+}
+
+fn decimated_collider(c: &mut Criterion) {
+    let density: Arc<dyn DensityFunction> = Arc::new(terrain());
+    let mut group = c.benchmark_group("generate_collider");
+    for size in CHUNK_SIZES {
+        for lod in [0, 1] {
+            group.bench_with_input(
+                BenchmarkId::new(format!("lod{lod}"), size),
+                &size,
+                |b, &size| {
+                    b.iter(|| generate_collider(density.clone(), Vec3::ZERO, size, lod, default()))
+                },
+            );
+        }
+    }
+    group.finish();
+}
+
+fn whole_chunk(c: &mut Criterion) {
+    let density: Arc<dyn DensityFunction> = Arc::new(terrain());
+    let mut group = c.benchmark_group("generate_world");
+    group.sample_size(20);
+    for size in CHUNK_SIZES {
+        for (name, welding) in [("edge", Welding::EdgeIndex), ("kd", Welding::KdTree)] {
+            group.bench_with_input(BenchmarkId::new(name, size), &size, |b, &size| {
+                b.iter(|| {
+                    generate_world(
+                        density.clone(),
+                        1,
+                        NormalMode::Faces,
+                        welding,
+                        Vec3::ZERO,
+                        size,
+                        0,
+                        default(),
+                    )
+                })
+            });
+        }
+    }
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    sampling,
+    noise,
+    marching,
+    kd_tree,
+    deduplication,
+    normals,
+    collider,
+    decimated_collider,
+    whole_chunk
+);
+criterion_main!(benches);
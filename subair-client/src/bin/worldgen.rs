@@ -0,0 +1,409 @@
+//! Generates a region of chunks without a window and exports the meshes
+//!
+//! `cargo run --bin worldgen -- --min -1,-1,-1 --max 1,1,1 --format gltf --output region.gltf`
+
+use bevy::prelude::*;
+use bevy::render::mesh::{Indices, VertexAttributeValues};
+use std::error::Error;
+use std::fmt::Write as _;
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Instant;
+use subair::world::density::DensityFunction;
+use subair::world::generate::{
+    classify_chunk, generate_world, ChunkContent, GeneratedChunk, GenerationStats, Welding,
+};
+use subair::world::preset::{WorldPreset, DEFAULT_PRESET};
+use subair::world::MAX_LOD;
+
+const USAGE: &str = "\
+Usage: worldgen [OPTIONS]
+
+Options:
+  --preset <PATH>       World preset to generate [default: assets/worlds/default.preset.ron]
+  --seed <SEED>         Seed used instead of the one in the preset
+  --min <X,Y,Z>         First chunk of the region [default: -1,-1,-1]
+  --max <X,Y,Z>         Last chunk of the region [default: 1,1,1]
+  --lod <LOD>           Level of detail of every chunk [default: 0]
+  --welding <edge|kd>   How vertices are merged, instead of the preset's
+  --format <obj|gltf>   Format of the exported meshes [default: obj]
+  --output <PATH>       Mesh file to write [default: worldgen.<format>]
+  --stats <PATH>        Also write the stats report to a file
+  --help                Print this message";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Format {
+    Obj,
+    Gltf,
+}
+
+#[derive(Debug)]
+struct Options {
+    preset: PathBuf,
+    seed: Option<u64>,
+    min: IVec3,
+    max: IVec3,
+    lod: u32,
+    welding: Option<Welding>,
+    format: Format,
+    output: Option<PathBuf>,
+    stats: Option<PathBuf>,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            preset: Path::new("assets").join(DEFAULT_PRESET),
+            seed: None,
+            min: IVec3::splat(-1),
+            max: IVec3::ONE,
+            lod: 0,
+            welding: None,
+            format: Format::Obj,
+            output: None,
+            stats: None,
+        }
+    }
+}
+
+fn parse_coord(value: &str) -> Result<IVec3, Box<dyn Error>> {
+    let components = value
+        .split(',')
+        .map(|component| component.trim().parse())
+        .collect::<Result<Vec<i32>, _>>()?;
+    match components[..] {
+        [x, y, z] => Ok(IVec3::new(x, y, z)),
+        _ => Err(format!("Expected a chunk coordinate like 0,-1,2, got {value}").into()),
+    }
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Option<Options>, Box<dyn Error>> {
+    let mut options = Options::default();
+    while let Some(arg) = args.next() {
+        if arg == "--help" || arg == "-h" {
+            return Ok(None);
+        }
+        let value = args
+            .next()
+            .ok_or_else(|| format!("Missing value for {arg}"))?;
+        match arg.as_str() {
+            "--preset" => options.preset = value.into(),
+            "--seed" => options.seed = Some(value.parse()?),
+            "--min" => options.min = parse_coord(&value)?,
+            "--max" => options.max = parse_coord(&value)?,
+            "--lod" => {
+                options.lod = value.parse()?;
+                if options.lod > MAX_LOD {
+                    return Err(format!(
+                        "LOD {} is coarser than the last LOD {MAX_LOD}",
+                        options.lod
+                    )
+                    .into());
+                }
+            }
+            "--welding" => {
+                options.welding = Some(match value.as_str() {
+                    "edge" => Welding::EdgeIndex,
+                    "kd" => Welding::KdTree,
+                    _ => return Err(format!("Unknown welding {value}").into()),
+                })
+            }
+            "--format" => {
+                options.format = match value.as_str() {
+                    "obj" => Format::Obj,
+                    "gltf" => Format::Gltf,
+                    _ => return Err(format!("Unknown format {value}").into()),
+                }
+            }
+            "--output" => options.output = Some(value.into()),
+            "--stats" => options.stats = Some(value.into()),
+            _ => return Err(format!("Unknown option {arg}").into()),
+        }
+    }
+    if options.min.cmpgt(options.max).any() {
+        return Err("--min has to be smaller than --max on every axis".into());
+    }
+    Ok(Some(options))
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let Some(options) = parse_args(std::env::args().skip(1))? else {
+        println!("{USAGE}");
+        return Ok(());
+    };
+    let mut preset = WorldPreset::from_ron(&fs::read(&options.preset)?)?;
+    if let Some(seed) = options.seed {
+        preset.seed = seed;
+    }
+    if let Some(welding) = options.welding {
+        preset.welding = welding;
+    }
+    let stride = 1 << options.lod;
+    if !preset.chunk_size.is_multiple_of(stride) {
+        return Err(format!("LOD {} is too coarse for the chunk size", options.lod).into());
+    }
+
+    let start = Instant::now();
+    let biomes = Arc::new(preset.build_biomes());
+    let density: Arc<dyn DensityFunction> = Arc::from(preset.build_density(biomes));
+    let mut chunks = vec![];
+    let mut skipped = Skipped::default();
+    for x in options.min.x..=options.max.x {
+        for y in options.min.y..=options.max.y {
+            for z in options.min.z..=options.max.z {
+                let coord = IVec3::new(x, y, z);
+                let offset = coord.as_vec3() * preset.chunk_size as f32;
+                match classify_chunk(density.as_ref(), offset, preset.chunk_size) {
+                    ChunkContent::Empty => {
+                        skipped.empty += 1;
+                        continue;
+                    }
+                    ChunkContent::Solid => {
+                        skipped.solid += 1;
+                        continue;
+                    }
+                    ChunkContent::Mixed => {}
+                }
+                let chunk = generate_world(
+                    density.clone(),
+                    preset.seed,
+                    preset.normal_mode,
+                    preset.welding,
+                    offset,
+                    preset.chunk_size,
+                    options.lod,
+                    Default::default(),
+                );
+                chunks.push((coord, chunk));
+            }
+        }
+    }
+    let elapsed = start.elapsed();
+
+    let output = options
+        .output
+        .clone()
+        .unwrap_or_else(|| match options.format {
+            Format::Obj => "worldgen.obj".into(),
+            Format::Gltf => "worldgen.gltf".into(),
+        });
+    match options.format {
+        Format::Obj => write_obj(&output, &chunks)?,
+        Format::Gltf => write_gltf(&output, &chunks)?,
+    }
+
+    let report = stats_report(&preset, &options, &chunks, skipped, elapsed.as_secs_f32());
+    print!("{report}");
+    println!("Wrote {}", output.display());
+    if let Some(path) = &options.stats {
+        fs::write(path, report)?;
+    }
+    Ok(())
+}
+
+/// Positions, normals and indices of a chunk mesh
+type MeshData<'a> = (&'a [[f32; 3]], &'a [[f32; 3]], &'a [u32]);
+
+fn mesh_data(chunk: &GeneratedChunk) -> std::io::Result<MeshData<'_>> {
+    let unexpected = |what| {
+        std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("Chunk mesh has {what} in an unexpected format"),
+        )
+    };
+    let Some(VertexAttributeValues::Float32x3(positions)) =
+        chunk.mesh.attribute(Mesh::ATTRIBUTE_POSITION)
+    else {
+        return Err(unexpected("positions"));
+    };
+    let Some(VertexAttributeValues::Float32x3(normals)) =
+        chunk.mesh.attribute(Mesh::ATTRIBUTE_NORMAL)
+    else {
+        return Err(unexpected("normals"));
+    };
+    let Some(Indices::U32(indices)) = chunk.mesh.indices() else {
+        return Err(unexpected("indices"));
+    };
+    Ok((positions, normals, indices))
+}
+
+/// Writes every chunk as an object, with its vertices in world space
+fn write_obj(path: &Path, chunks: &[(IVec3, GeneratedChunk)]) -> std::io::Result<()> {
+    let mut file = BufWriter::new(File::create(path)?);
+    writeln!(file, "# Generated by subair worldgen")?;
+    // Indices are global and start at 1
+    let mut first = 1;
+    for (coord, chunk) in chunks {
+        let (positions, normals, indices) = mesh_data(chunk)?;
+        if indices.is_empty() {
+            continue;
+        }
+        writeln!(file, "o chunk_{}_{}_{}", coord.x, coord.y, coord.z)?;
+        for position in positions {
+            let p = Vec3::from(*position) + chunk.offset;
+            writeln!(file, "v {} {} {}", p.x, p.y, p.z)?;
+        }
+        for n in normals {
+            writeln!(file, "vn {} {} {}", n[0], n[1], n[2])?;
+        }
+        for triangle in indices.chunks_exact(3) {
+            let [a, b, c] = [0, 1, 2].map(|i| triangle[i] + first);
+            writeln!(file, "f {a}//{a} {b}//{b} {c}//{c}")?;
+        }
+        first += positions.len() as u32;
+    }
+    file.flush()
+}
+
+/// Writes a glTF file with one node per chunk, the buffers go into a `.bin` file next to it
+fn write_gltf(path: &Path, chunks: &[(IVec3, GeneratedChunk)]) -> std::io::Result<()> {
+    let bin_path = path.with_extension("bin");
+    let bin_name = bin_path.file_name().unwrap().to_string_lossy();
+    let mut buffer: Vec<u8> = vec![];
+    let (mut views, mut accessors, mut meshes, mut nodes) = (vec![], vec![], vec![], vec![]);
+
+    let mut add_view = |buffer: &mut Vec<u8>, bytes: Vec<u8>, target: u32| {
+        views.push(format!(
+            r#"{{"buffer":0,"byteOffset":{},"byteLength":{},"target":{target}}}"#,
+            buffer.len(),
+            bytes.len()
+        ));
+        buffer.extend(bytes);
+        views.len() - 1
+    };
+    for (coord, chunk) in chunks {
+        let (positions, normals, indices) = mesh_data(chunk)?;
+        if indices.is_empty() {
+            continue;
+        }
+        let (min, max) = positions.iter().fold(
+            (Vec3::splat(f32::MAX), Vec3::splat(f32::MIN)),
+            |(min, max), p| (min.min(Vec3::from(*p)), max.max(Vec3::from(*p))),
+        );
+        let floats = |values: &[[f32; 3]]| -> Vec<u8> {
+            values
+                .iter()
+                .flatten()
+                .flat_map(|v| v.to_le_bytes())
+                .collect()
+        };
+        // 34962 is ARRAY_BUFFER and 34963 ELEMENT_ARRAY_BUFFER
+        let position_view = add_view(&mut buffer, floats(positions), 34962);
+        let normal_view = add_view(&mut buffer, floats(normals), 34962);
+        let index_bytes = indices.iter().flat_map(|i| i.to_le_bytes()).collect();
+        let index_view = add_view(&mut buffer, index_bytes, 34963);
+
+        let first = accessors.len();
+        // 5126 is FLOAT and 5125 UNSIGNED_INT
+        accessors.push(format!(
+            r#"{{"bufferView":{position_view},"componentType":5126,"count":{},"type":"VEC3","min":[{},{},{}],"max":[{},{},{}]}}"#,
+            positions.len(),
+            min.x, min.y, min.z, max.x, max.y, max.z
+        ));
+        accessors.push(format!(
+            r#"{{"bufferView":{normal_view},"componentType":5126,"count":{},"type":"VEC3"}}"#,
+            normals.len()
+        ));
+        accessors.push(format!(
+            r#"{{"bufferView":{index_view},"componentType":5125,"count":{},"type":"SCALAR"}}"#,
+            indices.len()
+        ));
+        meshes.push(format!(
+            r#"{{"primitives":[{{"attributes":{{"POSITION":{first},"NORMAL":{}}},"indices":{},"mode":4}}]}}"#,
+            first + 1,
+            first + 2
+        ));
+        let offset = chunk.offset;
+        nodes.push(format!(
+            r#"{{"name":"chunk_{}_{}_{}","mesh":{},"translation":[{},{},{}]}}"#,
+            coord.x,
+            coord.y,
+            coord.z,
+            meshes.len() - 1,
+            offset.x,
+            offset.y,
+            offset.z
+        ));
+    }
+
+    let node_indices: Vec<_> = (0..nodes.len()).map(|i| i.to_string()).collect();
+    let json = format!(
+        r#"{{"asset":{{"version":"2.0","generator":"subair worldgen"}},"scene":0,"scenes":[{{"nodes":[{}]}}],"nodes":[{}],"meshes":[{}],"accessors":[{}],"bufferViews":[{}],"buffers":[{{"byteLength":{},"uri":"{bin_name}"}}]}}"#,
+        node_indices.join(","),
+        nodes.join(","),
+        meshes.join(","),
+        accessors.join(","),
+        views.join(","),
+        buffer.len()
+    );
+    fs::write(&bin_path, buffer)?;
+    fs::write(path, json)
+}
+
+/// Chunks without a surface, which aren't generated
+#[derive(Debug, Clone, Copy, Default)]
+struct Skipped {
+    empty: usize,
+    solid: usize,
+}
+
+fn stats_report(
+    preset: &WorldPreset,
+    options: &Options,
+    chunks: &[(IVec3, GeneratedChunk)],
+    skipped: Skipped,
+    seconds: f32,
+) -> String {
+    let mut report = String::new();
+    let _ = writeln!(
+        report,
+        "seed {} | chunk size {} | lod {} | {:?} normals | {:?} welding | chunks {} to {}",
+        preset.seed,
+        preset.chunk_size,
+        options.lod,
+        preset.normal_mode,
+        preset.welding,
+        options.min,
+        options.max
+    );
+    let _ = writeln!(
+        report,
+        "{:<14} {:>12} {:>10} {:>7} {:>8} {:>10} {:>10}",
+        "chunk", "raw vertices", "vertices", "dedup", "skirts", "triangles", "time"
+    );
+    let mut total = GenerationStats::default();
+    let mut row = |name: String, stats: &GenerationStats| {
+        let _ = writeln!(
+            report,
+            "{:<14} {:>12} {:>10} {:>6.2}% {:>8} {:>10} {:>8.3}ms",
+            name,
+            stats.raw_vertices,
+            stats.vertices,
+            stats.dedup_ratio() * 100.0,
+            stats.skirt_vertices,
+            stats.triangles,
+            stats.duration.as_secs_f32() * 1000.0
+        );
+    };
+    for (coord, chunk) in chunks {
+        let stats = &chunk.stats;
+        row(format!("{},{},{}", coord.x, coord.y, coord.z), stats);
+        total.raw_vertices += stats.raw_vertices;
+        total.vertices += stats.vertices;
+        total.skirt_vertices += stats.skirt_vertices;
+        total.triangles += stats.triangles;
+        total.duration += stats.duration;
+    }
+    row("total".to_string(), &total);
+    let _ = writeln!(
+        report,
+        "Generated {} chunks in {:.3}ms, skipped {} empty and {} solid chunks",
+        chunks.len(),
+        seconds * 1000.0,
+        skipped.empty,
+        skipped.solid
+    );
+    report
+}
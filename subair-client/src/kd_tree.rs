@@ -0,0 +1,322 @@
+use bevy::prelude::*;
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+
+/// Points with a payload of any type, for finding the ones near a position.
+///
+/// The tree is stored implicitly in one array: every range of entries has its
+/// median along the axis of its depth in the middle, lesser entries before and
+/// greater ones after it. An empty tree is valid and never finds anything
+#[derive(Debug, Clone)]
+pub struct KdTree<T> {
+    entries: Vec<(Vec3, T)>,
+}
+
+/// Range of entries forming a subtree, with the depth of its root
+#[derive(Debug, Clone, Copy)]
+struct Node {
+    start: usize,
+    end: usize,
+    depth: usize,
+}
+
+impl Node {
+    fn middle(&self) -> usize {
+        (self.start + self.end) / 2
+    }
+
+    fn axis(&self) -> usize {
+        self.depth % 3
+    }
+
+    /// Lesser and greater children, which may be empty
+    fn children(&self) -> [Node; 2] {
+        let middle = self.middle();
+        let depth = self.depth + 1;
+        [
+            Node {
+                start: self.start,
+                end: middle,
+                depth,
+            },
+            Node {
+                start: middle + 1,
+                end: self.end,
+                depth,
+            },
+        ]
+    }
+
+    fn is_empty(&self) -> bool {
+        self.start >= self.end
+    }
+}
+
+impl<T> KdTree<T> {
+    pub fn new(entries: impl IntoIterator<Item = (Vec3, T)>) -> Self {
+        let mut entries: Vec<_> = entries.into_iter().collect();
+        let mut stack = vec![Node {
+            start: 0,
+            end: entries.len(),
+            depth: 0,
+        }];
+        while let Some(node) = stack.pop() {
+            if node.is_empty() {
+                continue;
+            }
+            let axis = node.axis();
+            entries[node.start..node.end]
+                .select_nth_unstable_by(node.middle() - node.start, |(a, _), (b, _)| {
+                    a[axis].total_cmp(&b[axis])
+                });
+            stack.extend(node.children());
+        }
+        Self { entries }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Every entry, in no particular order
+    pub fn iter(&self) -> impl Iterator<Item = (Vec3, &T)> {
+        self.entries.iter().map(|(point, item)| (*point, item))
+    }
+
+    fn root(&self) -> Node {
+        Node {
+            start: 0,
+            end: self.entries.len(),
+            depth: 0,
+        }
+    }
+
+    /// Calls `consumer` with every entry at most `radius` away from `point`
+    pub fn within_radius(&self, point: Vec3, radius: f32, mut consumer: impl FnMut(Vec3, &T)) {
+        let radius_squared = radius * radius;
+        let mut stack = vec![self.root()];
+        while let Some(node) = stack.pop() {
+            if node.is_empty() {
+                continue;
+            }
+            let (position, item) = &self.entries[node.middle()];
+            if position.distance_squared(point) <= radius_squared {
+                consumer(*position, item);
+            }
+            let axis = node.axis();
+            let [lesser, greater] = node.children();
+            if point[axis] - radius <= position[axis] {
+                stack.push(lesser);
+            }
+            if point[axis] + radius >= position[axis] {
+                stack.push(greater);
+            }
+        }
+    }
+
+    /// Calls `consumer` with every entry inside the box from `min` to `max`
+    pub fn within_aabb(&self, min: Vec3, max: Vec3, mut consumer: impl FnMut(Vec3, &T)) {
+        let mut stack = vec![self.root()];
+        while let Some(node) = stack.pop() {
+            if node.is_empty() {
+                continue;
+            }
+            let (position, item) = &self.entries[node.middle()];
+            if position.cmpge(min).all() && position.cmple(max).all() {
+                consumer(*position, item);
+            }
+            let axis = node.axis();
+            let [lesser, greater] = node.children();
+            if min[axis] <= position[axis] {
+                stack.push(lesser);
+            }
+            if max[axis] >= position[axis] {
+                stack.push(greater);
+            }
+        }
+    }
+
+    /// The entry closest to `point`, `None` if the tree is empty
+    pub fn nearest(&self, point: Vec3) -> Option<(Vec3, &T)> {
+        self.nearest_k(point, 1).into_iter().next()
+    }
+
+    /// The `k` entries closest to `point`, closest first
+    pub fn nearest_k(&self, point: Vec3, k: usize) -> Vec<(Vec3, &T)> {
+        if k == 0 {
+            return vec![];
+        }
+        // The furthest of the closest entries found so far is on top
+        let mut closest = BinaryHeap::with_capacity(k + 1);
+        // Subtrees with a lower bound of the squared distance to their entries
+        let mut stack = vec![(self.root(), 0.0)];
+        while let Some((node, bound)) = stack.pop() {
+            if node.is_empty() {
+                continue;
+            }
+            if closest.len() == k && closest.peek().is_some_and(|c: &Candidate| bound > c.0) {
+                continue;
+            }
+            let middle = node.middle();
+            let position = self.entries[middle].0;
+            closest.push(Candidate(position.distance_squared(point), middle));
+            if closest.len() > k {
+                closest.pop();
+            }
+            let axis = node.axis();
+            let offset = point[axis] - position[axis];
+            let [lesser, greater] = node.children();
+            let (near, far) = if offset < 0.0 {
+                (lesser, greater)
+            } else {
+                (greater, lesser)
+            };
+            // The near side is searched first, hopefully excluding the far one
+            stack.push((far, bound.max(offset * offset)));
+            stack.push((near, bound));
+        }
+        closest
+            .into_sorted_vec()
+            .into_iter()
+            .map(|Candidate(_, index)| {
+                let (position, item) = &self.entries[index];
+                (*position, item)
+            })
+            .collect()
+    }
+}
+
+/// Squared distance and index of an entry
+#[derive(Debug, Clone, Copy)]
+struct Candidate(f32, usize);
+
+impl PartialEq for Candidate {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Candidate {}
+
+impl PartialOrd for Candidate {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Candidate {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.total_cmp(&other.0).then(self.1.cmp(&other.1))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Deterministic points spread over a 20 unit cube, with some duplicates
+    fn points(count: usize) -> Vec<Vec3> {
+        let mut state = 0x2545_F491_4F6C_DD1Du64;
+        let mut next = || {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            (state % 2000) as f32 / 100.0 - 10.0
+        };
+        let mut points: Vec<_> = (0..count)
+            .map(|_| Vec3::new(next(), next(), next()))
+            .collect();
+        points.extend_from_within(..count / 10);
+        points
+    }
+
+    fn sorted(mut indices: Vec<usize>) -> Vec<usize> {
+        indices.sort_unstable();
+        indices
+    }
+
+    #[test]
+    fn empty_tree_finds_nothing() {
+        let tree = KdTree::<()>::new([]);
+        assert!(tree.is_empty());
+        assert!(tree.nearest(Vec3::ZERO).is_none());
+        assert!(tree.nearest_k(Vec3::ZERO, 3).is_empty());
+        tree.within_radius(Vec3::ZERO, 100.0, |_, _| panic!("Found a point"));
+        tree.within_aabb(Vec3::splat(-100.0), Vec3::splat(100.0), |_, _| {
+            panic!("Found a point")
+        });
+    }
+
+    #[test]
+    fn queries_match_brute_force() {
+        let points = points(500);
+        let tree = KdTree::new(points.iter().copied().enumerate().map(|(i, p)| (p, i)));
+        assert_eq!(tree.len(), points.len());
+
+        for query in [
+            Vec3::ZERO,
+            Vec3::new(3.2, -7.5, 9.9),
+            Vec3::splat(15.0),
+            points[42],
+        ] {
+            let mut by_distance: Vec<_> = (0..points.len()).collect();
+            by_distance.sort_by(|&a, &b| {
+                let a = (points[a].distance_squared(query), a);
+                let b = (points[b].distance_squared(query), b);
+                a.partial_cmp(&b).unwrap()
+            });
+
+            let (nearest, _) = tree.nearest(query).unwrap();
+            assert_eq!(
+                nearest.distance(query),
+                points[by_distance[0]].distance(query)
+            );
+
+            let found: Vec<_> = tree
+                .nearest_k(query, 10)
+                .into_iter()
+                .map(|(_, i)| *i)
+                .collect();
+            let distances = |indices: &[usize]| -> Vec<f32> {
+                indices
+                    .iter()
+                    .map(|&i| points[i].distance_squared(query))
+                    .collect()
+            };
+            assert_eq!(distances(&found), distances(&by_distance[..10]));
+
+            let mut found = vec![];
+            tree.within_radius(query, 4.0, |_, &i| found.push(i));
+            let expected: Vec<_> = (0..points.len())
+                .filter(|&i| points[i].distance(query) <= 4.0)
+                .collect();
+            assert_eq!(sorted(found), expected);
+
+            let (min, max) = (
+                query - Vec3::new(2.0, 5.0, 3.0),
+                query + Vec3::new(4.0, 1.0, 3.0),
+            );
+            let mut found = vec![];
+            tree.within_aabb(min, max, |_, &i| found.push(i));
+            let expected: Vec<_> = (0..points.len())
+                .filter(|&i| points[i].cmpge(min).all() && points[i].cmple(max).all())
+                .collect();
+            assert_eq!(sorted(found), expected);
+        }
+    }
+
+    #[test]
+    fn nearest_k_returns_every_entry_when_short() {
+        let tree = KdTree::new([(Vec3::X, 'a'), (Vec3::Y * 3.0, 'b'), (Vec3::Z * 2.0, 'c')]);
+        let found: Vec<_> = tree
+            .nearest_k(Vec3::ZERO, 5)
+            .into_iter()
+            .map(|(_, c)| *c)
+            .collect();
+        assert_eq!(found, ['a', 'c', 'b']);
+    }
+}
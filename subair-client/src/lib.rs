@@ -0,0 +1,3 @@
+pub mod kd_tree;
+pub mod player;
+pub mod world;
@@ -0,0 +1,205 @@
+use super::input::{Action, ActionState};
+use super::Controlled;
+use bevy::prelude::*;
+use bevy_rapier3d::prelude::*;
+use std::f32::consts::FRAC_PI_2;
+use tracing::info;
+
+// Distance the camera keeps from the terrain
+const TERRAIN_MARGIN: f32 = 0.3;
+// Keeps the orbit from flipping over the top
+const MAX_ORBIT_PITCH: f32 = FRAC_PI_2 - 0.05;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Reflect)]
+pub enum CameraMode {
+    /// Inside the sub, looking where it points
+    #[default]
+    Cockpit,
+    /// Behind the sub, following it smoothly
+    Chase,
+    /// Free orbit around the sub, which keeps its heading while the player looks around
+    Orbit,
+}
+
+impl CameraMode {
+    fn next(self) -> Self {
+        match self {
+            CameraMode::Cockpit => CameraMode::Chase,
+            CameraMode::Chase => CameraMode::Orbit,
+            CameraMode::Orbit => CameraMode::Cockpit,
+        }
+    }
+}
+
+#[derive(Debug, Component, Reflect, Default)]
+pub struct PlayerCamera {
+    pub mode: CameraMode,
+    orbit_yaw: f32,
+    orbit_pitch: f32,
+}
+
+impl PlayerCamera {
+    pub fn orbiting(&self) -> bool {
+        self.mode == CameraMode::Orbit
+    }
+}
+
+#[derive(Debug, Resource, Reflect)]
+pub struct CameraSettings {
+    /// Position of the cockpit camera relative to the sub
+    pub cockpit_offset: Vec3,
+    /// Position of the chase camera relative to the sub
+    pub chase_offset: Vec3,
+    /// How quickly the chase camera catches up with the sub, per second
+    pub chase_stiffness: f32,
+    pub orbit_distance: f32,
+}
+
+impl Default for CameraSettings {
+    fn default() -> Self {
+        Self {
+            cockpit_offset: Vec3::ZERO,
+            chase_offset: Vec3::new(0.0, 1.5, 6.0),
+            chase_stiffness: 5.0,
+            orbit_distance: 8.0,
+        }
+    }
+}
+
+/// The loaded model of the sub, hidden in the cockpit
+#[derive(Debug, Component)]
+pub struct SubModel;
+
+pub fn spawn_camera(mut commands: Commands) {
+    commands.spawn((
+        PlayerCamera::default(),
+        Camera3dBundle::default(),
+        FogSettings {
+            color: Color::rgb(0.0, 0.0, 0.5),
+            falloff: FogFalloff::from_visibility_color(150.0, Color::rgb(0.0, 0.0, 0.9)),
+            ..default()
+        },
+    ));
+}
+
+pub fn cycle_camera_mode(
+    actions: Res<ActionState>,
+    mut cameras: Query<(&mut PlayerCamera, &Transform)>,
+    mut models: Query<&mut Visibility, With<SubModel>>,
+) {
+    for (mut camera, transform) in cameras.iter_mut() {
+        if actions.just_pressed(Action::CycleCamera) {
+            camera.mode = camera.mode.next();
+            // The orbit starts where the camera is looking
+            let (yaw, pitch, _) = transform.rotation.to_euler(EulerRot::YXZ);
+            camera.orbit_yaw = yaw;
+            camera.orbit_pitch = pitch.clamp(-MAX_ORBIT_PITCH, MAX_ORBIT_PITCH);
+            info!(mode = ?camera.mode, "Switched camera");
+        }
+        let visibility = match camera.mode {
+            CameraMode::Cockpit => Visibility::Hidden,
+            CameraMode::Chase | CameraMode::Orbit => Visibility::Inherited,
+        };
+        for mut model in models.iter_mut() {
+            if *model != visibility {
+                *model = visibility;
+            }
+        }
+    }
+}
+
+/// Moves the camera after physics has moved the sub, so it doesn't lag a frame behind
+#[allow(clippy::type_complexity)]
+pub fn follow_player(
+    settings: Res<CameraSettings>,
+    actions: Res<ActionState>,
+    rapier: Res<RapierContext>,
+    time: Res<Time>,
+    player: Query<&Transform, (With<Controlled>, Without<PlayerCamera>)>,
+    mut cameras: Query<(&mut PlayerCamera, &mut Transform)>,
+) {
+    let Ok(sub) = player.get_single() else { return };
+    // Only terrain blocks the view, the sub itself is dynamic
+    let cast = |origin, direction, distance| {
+        rapier
+            .cast_ray(origin, direction, distance, true, QueryFilter::only_fixed())
+            .map(|(_, toi)| toi)
+    };
+    let delta = time.delta_seconds();
+    for (mut camera, mut transform) in cameras.iter_mut() {
+        match camera.mode {
+            CameraMode::Cockpit => {
+                transform.translation = sub.transform_point(settings.cockpit_offset);
+                transform.rotation = sub.rotation;
+            }
+            CameraMode::Chase => {
+                let target = sub.transform_point(settings.chase_offset);
+                let t = 1.0 - (-settings.chase_stiffness * delta).exp();
+                let position = transform.translation.lerp(target, t);
+                transform.translation = avoid_terrain(sub.translation, position, cast);
+                transform.look_at(sub.translation, sub.up());
+            }
+            CameraMode::Orbit => {
+                let look = actions.axis_pair(Action::Look);
+                camera.orbit_yaw -= look.x * delta;
+                camera.orbit_pitch =
+                    (camera.orbit_pitch + look.y * delta).clamp(-MAX_ORBIT_PITCH, MAX_ORBIT_PITCH);
+                let rotation =
+                    Quat::from_euler(EulerRot::YXZ, camera.orbit_yaw, camera.orbit_pitch, 0.0);
+                let position = sub.translation + rotation * Vec3::Z * settings.orbit_distance;
+                transform.translation = avoid_terrain(sub.translation, position, cast);
+                transform.rotation = rotation;
+            }
+        }
+    }
+}
+
+/// Moves the camera from `position` towards `target` until nothing that
+/// `cast` hits lies between them
+fn avoid_terrain(
+    target: Vec3,
+    position: Vec3,
+    cast: impl Fn(Vec3, Vec3, f32) -> Option<f32>,
+) -> Vec3 {
+    let offset = position - target;
+    let distance = offset.length();
+    if distance <= TERRAIN_MARGIN {
+        return position;
+    }
+    let direction = offset / distance;
+    match cast(target, direction, distance + TERRAIN_MARGIN) {
+        Some(toi) => target + direction * (toi - TERRAIN_MARGIN).clamp(0.0, distance),
+        None => position,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn camera_stays_in_front_of_walls() {
+        // A wall at z = 4
+        let cast = |origin: Vec3, direction: Vec3, distance: f32| {
+            let toi = (4.0 - origin.z) / direction.z;
+            (toi >= 0.0 && toi <= distance).then_some(toi)
+        };
+        let target = Vec3::ZERO;
+        let behind_wall = avoid_terrain(target, Vec3::new(0.0, 0.0, 6.0), cast);
+        assert!((behind_wall.z - (4.0 - TERRAIN_MARGIN)).abs() < 1.0e-5);
+        // Too close to the wall the camera moves away from it as well
+        let at_wall = avoid_terrain(target, Vec3::new(0.0, 0.0, 3.9), cast);
+        assert!(at_wall.z <= 4.0 - TERRAIN_MARGIN + 1.0e-5);
+        let clear = Vec3::new(0.0, 1.0, 2.0);
+        assert_eq!(avoid_terrain(target, clear, cast), clear);
+    }
+
+    #[test]
+    fn modes_cycle() {
+        let mut mode = CameraMode::default();
+        for _ in 0..3 {
+            mode = mode.next();
+        }
+        assert_eq!(mode, CameraMode::default());
+    }
+}
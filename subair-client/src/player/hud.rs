@@ -0,0 +1,118 @@
+use super::submarine::{EngineTelemetry, Submarine, SubmarineParams};
+use bevy::prelude::*;
+
+const GAUGE_WIDTH: f32 = 200.0;
+const GAUGE_HEIGHT: f32 = 10.0;
+const MARGIN: f32 = 24.0;
+const REVERSE_COLOR: Color = Color::rgb(0.9, 0.4, 0.2);
+
+#[derive(Debug, Component)]
+pub struct Hud;
+
+/// Fill of a bar showing part of the engine telemetry
+#[derive(Debug, Clone, Copy, Component)]
+pub enum Gauge {
+    Throttle,
+    Rpm,
+    Power,
+}
+
+impl Gauge {
+    fn color(&self) -> Color {
+        match self {
+            Gauge::Throttle => Color::rgb(0.3, 0.8, 0.9),
+            Gauge::Rpm => Color::rgb(0.4, 0.9, 0.5),
+            Gauge::Power => Color::rgb(0.9, 0.8, 0.3),
+        }
+    }
+
+    /// How full the gauge is, negative in reverse
+    fn fraction(&self, params: &SubmarineParams, sub: &Submarine, engine: &EngineTelemetry) -> f32 {
+        match self {
+            Gauge::Throttle => sub.throttle,
+            Gauge::Rpm => engine.rpm / params.max_rpm,
+            Gauge::Power => {
+                let max_power = params.max_power + 2.0 * params.thruster_power;
+                engine.power_draw * 1000.0 / max_power
+            }
+        }
+    }
+}
+
+pub fn spawn_hud(mut commands: Commands) {
+    commands
+        .spawn((
+            Hud,
+            NodeBundle {
+                style: Style {
+                    position_type: PositionType::Absolute,
+                    position: UiRect {
+                        left: Val::Px(MARGIN),
+                        bottom: Val::Px(MARGIN),
+                        ..default()
+                    },
+                    flex_direction: FlexDirection::Column,
+                    gap: Size::height(Val::Px(GAUGE_HEIGHT / 2.0)),
+                    ..default()
+                },
+                // Shown once the world is loaded
+                visibility: Visibility::Hidden,
+                ..default()
+            },
+        ))
+        .with_children(|parent| {
+            for gauge in [Gauge::Throttle, Gauge::Rpm, Gauge::Power] {
+                parent
+                    .spawn(NodeBundle {
+                        style: Style {
+                            size: Size::new(Val::Px(GAUGE_WIDTH), Val::Px(GAUGE_HEIGHT)),
+                            ..default()
+                        },
+                        background_color: Color::rgba(0.1, 0.1, 0.3, 0.6).into(),
+                        ..default()
+                    })
+                    .with_children(|parent| {
+                        parent.spawn((
+                            gauge,
+                            NodeBundle {
+                                style: Style {
+                                    size: Size::new(Val::Percent(0.0), Val::Percent(100.0)),
+                                    ..default()
+                                },
+                                background_color: gauge.color().into(),
+                                ..default()
+                            },
+                        ));
+                    });
+            }
+        });
+}
+
+pub fn show_hud(mut huds: Query<&mut Visibility, With<Hud>>) {
+    for mut visibility in huds.iter_mut() {
+        *visibility = Visibility::Inherited;
+    }
+}
+
+pub fn hide_hud(mut huds: Query<&mut Visibility, With<Hud>>) {
+    for mut visibility in huds.iter_mut() {
+        *visibility = Visibility::Hidden;
+    }
+}
+
+pub fn update_hud(
+    subs: Query<(&SubmarineParams, &Submarine, &EngineTelemetry)>,
+    mut gauges: Query<(&Gauge, &mut Style, &mut BackgroundColor)>,
+) {
+    let Ok((params, sub, engine)) = subs.get_single() else { return };
+    for (gauge, mut style, mut color) in gauges.iter_mut() {
+        let fraction = gauge.fraction(params, sub, engine);
+        style.size.width = Val::Percent(fraction.abs().min(1.0) * 100.0);
+        *color = if fraction < 0.0 {
+            REVERSE_COLOR
+        } else {
+            gauge.color()
+        }
+        .into();
+    }
+}
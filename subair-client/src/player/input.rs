@@ -0,0 +1,358 @@
+use bevy::{
+    app::AppExit,
+    asset::{AssetLoader, LoadContext, LoadedAsset},
+    input::mouse::MouseMotion,
+    prelude::*,
+    reflect::TypeUuid,
+    utils::{BoxedFuture, HashMap, HashSet},
+    window::CursorGrabMode,
+};
+use serde::Deserialize;
+
+pub const DEFAULT_BINDINGS: &str = "input/default.bindings.ron";
+// Analog inputs past this count as pressed
+const PRESS_THRESHOLD: f32 = 0.5;
+
+/// Everything the player can do, independent of the device
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize)]
+pub enum Action {
+    /// Propeller, positive is ahead
+    Thrust,
+    /// Steps of the throttle while it is stepped
+    ThrottleUp,
+    ThrottleDown,
+    /// Positive is right
+    Strafe,
+    /// Positive is up
+    Ascend,
+    /// Positive rolls right
+    Roll,
+    /// Positive floods the ballast tanks, negative blows them
+    Ballast,
+    /// Turning, in radians per second with positive x to the right and positive y up
+    Look,
+    Drill,
+    Deposit,
+    CycleCamera,
+    CaptureCursor,
+    ReleaseCursor,
+    Quit,
+}
+
+/// A single input on any device
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+pub enum Binding {
+    Key(KeyCode),
+    Mouse(MouseButton),
+    /// Movement of the mouse on both axes
+    MouseMotion,
+    GamepadButton(GamepadButtonType),
+    /// How far an analog button like a trigger is pressed, from 0 to 1
+    GamepadTrigger(GamepadButtonType),
+    GamepadAxis(GamepadAxisType),
+    /// Both axes of a stick
+    GamepadStick(Stick),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub enum Stick {
+    Left,
+    Right,
+}
+
+/// Inputs that push an action in either direction
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct ActionBindings {
+    #[serde(default)]
+    pub positive: Vec<Binding>,
+    #[serde(default)]
+    pub negative: Vec<Binding>,
+}
+
+/// How the motion of a device turns into looking around
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub struct LookSettings {
+    pub sensitivity: f32,
+    #[serde(default)]
+    pub invert_x: bool,
+    #[serde(default)]
+    pub invert_y: bool,
+}
+
+impl LookSettings {
+    fn apply(&self, motion: Vec2) -> Vec2 {
+        let invert = |inverted| if inverted { -1.0 } else { 1.0 };
+        motion * self.sensitivity * Vec2::new(invert(self.invert_x), invert(self.invert_y))
+    }
+}
+
+/// Bindings of every action, loaded from a `.bindings.ron` file
+#[derive(Debug, Clone, Deserialize, TypeUuid)]
+#[uuid = "2d9f4c1b-7a8e-4f61-b3d2-5c0e9a7f1e48"]
+pub struct InputBindings {
+    pub actions: HashMap<Action, ActionBindings>,
+    pub mouse: LookSettings,
+    pub gamepad: LookSettings,
+}
+
+impl InputBindings {
+    pub fn from_ron(bytes: &[u8]) -> Result<InputBindings, bevy::asset::Error> {
+        Ok(ron::de::from_bytes(bytes)?)
+    }
+}
+
+#[derive(Default)]
+pub struct InputBindingsLoader;
+
+impl AssetLoader for InputBindingsLoader {
+    fn load<'a>(
+        &'a self,
+        bytes: &'a [u8],
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<(), bevy::asset::Error>> {
+        Box::pin(async move {
+            let bindings = InputBindings::from_ron(bytes)?;
+            load_context.set_default_asset(LoadedAsset::new(bindings));
+            Ok(())
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["bindings.ron"]
+    }
+}
+
+#[derive(Debug, Resource)]
+pub struct InputBindingsHandle(pub Handle<InputBindings>);
+
+pub fn load_bindings(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands.insert_resource(InputBindingsHandle(asset_server.load(DEFAULT_BINDINGS)));
+}
+
+/// Value of every action this frame
+#[derive(Debug, Resource, Default)]
+pub struct ActionState {
+    values: HashMap<Action, Vec2>,
+    pressed: HashSet<Action>,
+    just_pressed: HashSet<Action>,
+}
+
+impl ActionState {
+    /// Value of a one dimensional action, from -1 to 1
+    pub fn value(&self, action: Action) -> f32 {
+        self.axis_pair(action).x.clamp(-1.0, 1.0)
+    }
+
+    pub fn axis_pair(&self, action: Action) -> Vec2 {
+        self.values.get(&action).copied().unwrap_or_default()
+    }
+
+    pub fn pressed(&self, action: Action) -> bool {
+        self.pressed.contains(&action)
+    }
+
+    pub fn just_pressed(&self, action: Action) -> bool {
+        self.just_pressed.contains(&action)
+    }
+
+    fn set(&mut self, action: Action, value: Vec2) {
+        let pressed = value.x.abs() >= PRESS_THRESHOLD;
+        if pressed && !self.pressed.contains(&action) {
+            self.just_pressed.insert(action);
+        }
+        if pressed {
+            self.pressed.insert(action);
+        } else {
+            self.pressed.remove(&action);
+        }
+        self.values.insert(action, value);
+    }
+}
+
+/// Current state of every device
+struct Devices<'a> {
+    keys: &'a Input<KeyCode>,
+    mouse_buttons: &'a Input<MouseButton>,
+    mouse_motion: Vec2,
+    gamepads: Vec<Gamepad>,
+    buttons: &'a Input<GamepadButton>,
+    button_axes: &'a Axis<GamepadButton>,
+    axes: &'a Axis<GamepadAxis>,
+}
+
+impl Devices<'_> {
+    fn value(&self, binding: Binding, bindings: &InputBindings) -> Vec2 {
+        let digital = |pressed: bool| if pressed { Vec2::X } else { Vec2::ZERO };
+        match binding {
+            Binding::Key(key) => digital(self.keys.pressed(key)),
+            Binding::Mouse(button) => digital(self.mouse_buttons.pressed(button)),
+            // Moving the mouse up looks up
+            Binding::MouseMotion => bindings
+                .mouse
+                .apply(self.mouse_motion * Vec2::new(1.0, -1.0)),
+            Binding::GamepadButton(button) => digital(
+                self.gamepads
+                    .iter()
+                    .any(|gamepad| self.buttons.pressed(GamepadButton::new(*gamepad, button))),
+            ),
+            Binding::GamepadTrigger(button) => {
+                Vec2::X
+                    * self.sum(|gamepad| self.button_axes.get(GamepadButton::new(gamepad, button)))
+            }
+            Binding::GamepadAxis(axis) => {
+                Vec2::X * self.sum(|gamepad| self.axes.get(GamepadAxis::new(gamepad, axis)))
+            }
+            Binding::GamepadStick(stick) => {
+                let (x, y) = match stick {
+                    Stick::Left => (GamepadAxisType::LeftStickX, GamepadAxisType::LeftStickY),
+                    Stick::Right => (GamepadAxisType::RightStickX, GamepadAxisType::RightStickY),
+                };
+                let motion = Vec2::new(
+                    self.sum(|gamepad| self.axes.get(GamepadAxis::new(gamepad, x))),
+                    self.sum(|gamepad| self.axes.get(GamepadAxis::new(gamepad, y))),
+                );
+                bindings.gamepad.apply(motion)
+            }
+        }
+    }
+
+    fn sum(&self, value: impl Fn(Gamepad) -> Option<f32>) -> f32 {
+        self.gamepads
+            .iter()
+            .filter_map(|gamepad| value(*gamepad))
+            .sum()
+    }
+}
+
+/// Value of `bindings` given the value of every binding
+fn action_value(bindings: &ActionBindings, value: impl Fn(Binding) -> Vec2) -> Vec2 {
+    let positive: Vec2 = bindings
+        .positive
+        .iter()
+        .map(|binding| value(*binding))
+        .sum();
+    let negative: Vec2 = bindings
+        .negative
+        .iter()
+        .map(|binding| value(*binding))
+        .sum();
+    positive - negative
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn update_actions(
+    handle: Option<Res<InputBindingsHandle>>,
+    bindings: Res<Assets<InputBindings>>,
+    keys: Res<Input<KeyCode>>,
+    mouse_buttons: Res<Input<MouseButton>>,
+    mut mouse: EventReader<MouseMotion>,
+    gamepads: Res<Gamepads>,
+    buttons: Res<Input<GamepadButton>>,
+    button_axes: Res<Axis<GamepadButton>>,
+    axes: Res<Axis<GamepadAxis>>,
+    mut state: ResMut<ActionState>,
+) {
+    let devices = Devices {
+        keys: &keys,
+        mouse_buttons: &mouse_buttons,
+        mouse_motion: mouse.iter().map(|event| event.delta).sum(),
+        gamepads: gamepads.iter().collect(),
+        buttons: &buttons,
+        button_axes: &button_axes,
+        axes: &axes,
+    };
+    state.just_pressed.clear();
+    // Nothing is bound until the bindings are loaded
+    let Some(bindings) = handle.and_then(|handle| bindings.get(&handle.0)) else { return };
+    for (action, action_bindings) in &bindings.actions {
+        let value = action_value(action_bindings, |binding| devices.value(binding, bindings));
+        state.set(*action, value);
+    }
+}
+
+pub fn capture_cursor(mut windows: Query<&mut Window>, actions: Res<ActionState>) {
+    if actions.just_pressed(Action::CaptureCursor) {
+        for mut win in windows.iter_mut() {
+            win.cursor.visible = false;
+            win.cursor.grab_mode = CursorGrabMode::Locked;
+        }
+    }
+    if actions.pressed(Action::ReleaseCursor) {
+        for mut win in windows.iter_mut() {
+            win.cursor.visible = true;
+            win.cursor.grab_mode = CursorGrabMode::None;
+        }
+    }
+}
+
+pub fn quit(actions: Res<ActionState>, mut exit: EventWriter<AppExit>) {
+    if actions.just_pressed(Action::Quit) {
+        exit.send(AppExit);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn default_bindings() -> InputBindings {
+        InputBindings::from_ron(include_bytes!("../../../assets/input/default.bindings.ron"))
+            .unwrap()
+    }
+
+    #[test]
+    fn default_bindings_cover_every_action() {
+        let bindings = default_bindings();
+        for action in [
+            Action::Thrust,
+            Action::ThrottleUp,
+            Action::ThrottleDown,
+            Action::Strafe,
+            Action::Ascend,
+            Action::Roll,
+            Action::Ballast,
+            Action::Look,
+            Action::Drill,
+            Action::Deposit,
+            Action::CycleCamera,
+            Action::CaptureCursor,
+            Action::ReleaseCursor,
+            Action::Quit,
+        ] {
+            assert!(bindings.actions.contains_key(&action), "{action:?}");
+        }
+    }
+
+    #[test]
+    fn opposite_bindings_cancel() {
+        let bindings = default_bindings();
+        let thrust = &bindings.actions[&Action::Thrust];
+        let pressed = |keys: &'static [KeyCode]| {
+            move |binding: Binding| match binding {
+                Binding::Key(key) if keys.contains(&key) => Vec2::X,
+                _ => Vec2::ZERO,
+            }
+        };
+        assert_eq!(action_value(thrust, pressed(&[KeyCode::W])), Vec2::X);
+        assert_eq!(action_value(thrust, pressed(&[KeyCode::S])), Vec2::NEG_X);
+        assert_eq!(
+            action_value(thrust, pressed(&[KeyCode::W, KeyCode::S])),
+            Vec2::ZERO
+        );
+
+        let mut state = ActionState::default();
+        state.set(Action::Thrust, Vec2::X * 2.0);
+        assert_eq!(state.value(Action::Thrust), 1.0);
+        assert!(state.just_pressed(Action::Thrust));
+    }
+
+    #[test]
+    fn look_inverts_per_device() {
+        let settings = LookSettings {
+            sensitivity: 2.0,
+            invert_x: false,
+            invert_y: true,
+        };
+        assert_eq!(settings.apply(Vec2::new(1.0, 1.0)), Vec2::new(2.0, -2.0));
+    }
+}
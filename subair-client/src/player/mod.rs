@@ -0,0 +1,382 @@
+use bevy::{prelude::*, transform::TransformSystem};
+use bevy_inspector_egui::quick::ResourceInspectorPlugin;
+use bevy_rapier3d::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::f32::consts::PI;
+use tracing::info;
+
+use crate::world::{EditMode, EditShape, GameState, TerrainEdit};
+use camera::{CameraSettings, PlayerCamera, SubModel};
+use input::{Action, ActionState, InputBindings, InputBindingsLoader};
+use submarine::{submarine_bundle, BodyState, EngineTelemetry, Submarine, SubmarineParams, Water};
+
+mod camera;
+mod hud;
+pub mod input;
+mod submarine;
+
+const DRILL_INTERVAL: f32 = 0.1;
+const DRILL_DISTANCE: f32 = 3.0;
+const DRILL_RADIUS: f32 = 2.5;
+const DRILL_STRENGTH: f32 = 0.5;
+
+pub struct PlayerPlugin;
+
+impl Plugin for PlayerPlugin {
+    fn build(&self, app: &mut App) {
+        app.register_type::<Player>()
+            .register_type::<CalculatedInput>()
+            .register_type::<Controlled>()
+            .register_type::<ControlSettings>()
+            .register_type::<PlayerCamera>()
+            .register_type::<CameraSettings>()
+            .register_type::<Submarine>()
+            .register_type::<EngineTelemetry>()
+            .register_type::<SubmarineParams>()
+            .register_type::<Water>()
+            .insert_resource(CalculatedInput::default())
+            .init_resource::<ControlSettings>()
+            .init_resource::<CameraSettings>()
+            .init_resource::<Water>()
+            .init_resource::<ActionState>()
+            .add_asset::<InputBindings>()
+            .init_asset_loader::<InputBindingsLoader>()
+            .add_startup_system(input::load_bindings)
+            .add_system(input::update_actions)
+            .add_system(input::capture_cursor.after(input::update_actions))
+            .add_system(input::quit.after(input::update_actions))
+            .add_plugin(ResourceInspectorPlugin::<CalculatedInput>::new())
+            .add_system(register_propeller)
+            .add_startup_system(spawn_player)
+            .add_startup_system(camera::spawn_camera)
+            .add_system(camera::cycle_camera_mode.after(input::update_actions))
+            .add_system(
+                camera::follow_player
+                    .in_base_set(CoreSet::PostUpdate)
+                    .after(PhysicsSet::Writeback)
+                    .before(TransformSystem::TransformPropagate),
+            )
+            .add_system(update_input.after(input::update_actions))
+            // The player is held in place until the terrain around it is loaded
+            .add_systems(
+                (
+                    calculate_rotation.after(update_input),
+                    submarine_forces.after(calculate_rotation),
+                    drill,
+                )
+                    .distributive_run_if(in_state(GameState::Playing)),
+            )
+            .add_system(hold_submarine.run_if(in_state(GameState::Loading)))
+            .add_system(rotate_propeller)
+            .add_startup_system(hud::spawn_hud)
+            .add_system(hud::show_hud.in_schedule(OnEnter(GameState::Playing)))
+            .add_system(hud::hide_hud.in_schedule(OnExit(GameState::Playing)))
+            .add_system(
+                hud::update_hud
+                    .after(submarine_forces)
+                    .run_if(in_state(GameState::Playing)),
+            );
+    }
+}
+
+#[derive(Debug, Component, Reflect)]
+struct Player {
+    body_color: Color,
+    band_color: Color,
+}
+
+#[derive(Debug, Component)]
+struct Propeller;
+
+#[derive(Debug, Resource, Reflect, Default)]
+struct CalculatedInput {
+    vertical: f32,
+    horizontal: f32,
+    forward: f32,
+    /// Steps of the throttle this frame, positive speeds up
+    throttle_steps: f32,
+    /// Positive moves right
+    strafe: f32,
+    /// Positive moves up
+    ascend: f32,
+    /// Positive rolls right
+    roll: f32,
+    /// Positive floods the ballast tanks, negative blows them
+    ballast: f32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Reflect)]
+enum ThrottleMode {
+    /// The throttle follows the thrust input and returns to idle when it is released
+    Analog,
+    /// The throttle stays where it is set in steps
+    Stepped,
+}
+
+/// How the player steers the sub
+#[derive(Debug, Resource, Reflect)]
+struct ControlSettings {
+    throttle: ThrottleMode,
+    /// Change of the stepped throttle per step
+    throttle_step: f32,
+    /// Without roll the sub always levels itself
+    allow_roll: bool,
+    /// Radians per second
+    roll_speed: f32,
+    /// How quickly the sub levels itself while not rolling, per second
+    auto_level_rate: f32,
+}
+
+impl Default for ControlSettings {
+    fn default() -> Self {
+        Self {
+            throttle: ThrottleMode::Analog,
+            throttle_step: 0.25,
+            allow_roll: true,
+            roll_speed: 1.5,
+            auto_level_rate: 2.0,
+        }
+    }
+}
+
+/// Orientation the sub steers towards
+#[derive(Debug, Clone, PartialEq, Component, Reflect, Default, Serialize, Deserialize)]
+pub struct Controlled {
+    orientation: Quat,
+}
+
+fn spawn_player(mut commands: Commands, asset_server: Res<AssetServer>, water: Res<Water>) {
+    commands
+        .spawn(Controlled::default())
+        .insert(SpatialBundle::default())
+        .insert((
+            Collider::ball(0.8),
+            // Collider::capsule_z(0.8, 0.5),
+            submarine_bundle(SubmarineParams::default(), &water),
+        ))
+        .with_children(|b| {
+            b.spawn(SceneBundle {
+                scene: asset_server.load("player.glb#Scene0"),
+                transform: Transform::from_rotation(Quat::from_rotation_y(PI / -2.0)),
+                ..default()
+            })
+            .insert(SubModel);
+
+            b.spawn(SpotLightBundle {
+                spot_light: SpotLight {
+                    intensity: 20000.0,
+                    range: 100.0,
+                    outer_angle: PI / 6.0,
+                    color: Color::rgb(1.0, 0.9, 0.7),
+                    ..default()
+                },
+                transform: Transform::from_xyz(0.0, 0.0, -1.0),
+                ..default()
+            });
+        });
+}
+
+fn update_input(
+    actions: Res<ActionState>,
+    cameras: Query<&PlayerCamera>,
+    mut calcd: ResMut<CalculatedInput>,
+) {
+    // While orbiting, looking around moves the camera instead of steering
+    let orbiting = cameras.iter().any(PlayerCamera::orbiting);
+    let look = if orbiting {
+        Vec2::ZERO
+    } else {
+        actions.axis_pair(Action::Look)
+    };
+    calcd.forward = actions.value(Action::Thrust);
+    let step = |action, value| {
+        if actions.just_pressed(action) {
+            value
+        } else {
+            0.0
+        }
+    };
+    calcd.throttle_steps = step(Action::ThrottleUp, 1.0) + step(Action::ThrottleDown, -1.0);
+    calcd.strafe = actions.value(Action::Strafe);
+    calcd.ascend = actions.value(Action::Ascend);
+    calcd.roll = actions.value(Action::Roll);
+    calcd.ballast = actions.value(Action::Ballast);
+    calcd.horizontal = -look.x;
+    calcd.vertical = look.y;
+}
+
+fn calculate_rotation(
+    input: Res<CalculatedInput>,
+    settings: Res<ControlSettings>,
+    mut query: Query<&mut Controlled>,
+    time: Res<Time>,
+) {
+    let delta = time.delta_seconds();
+    let roll = if settings.allow_roll { input.roll } else { 0.0 };
+    // Turning around the sub's own axes, so looking straight up or down doesn't lock the yaw
+    let turn = Quat::from_rotation_y(input.horizontal * delta)
+        * Quat::from_rotation_x(input.vertical * delta)
+        * Quat::from_rotation_z(-roll * settings.roll_speed * delta);
+    for mut controlled in query.iter_mut() {
+        let mut orientation = (controlled.orientation * turn).normalize();
+        if roll == 0.0 {
+            let t = 1.0 - (-settings.auto_level_rate * delta).exp();
+            orientation = level(orientation, t);
+        }
+        controlled.orientation = orientation;
+    }
+}
+
+/// Rolls `orientation` the fraction `t` of the way to having its sides level
+fn level(orientation: Quat, t: f32) -> Quat {
+    let forward = orientation * Vec3::NEG_Z;
+    let level_right = forward.cross(Vec3::Y);
+    // Looking straight up or down any roll is level
+    if level_right.length_squared() < 1.0e-4 {
+        return orientation;
+    }
+    let right = orientation * Vec3::X;
+    let level_right = level_right.normalize();
+    let angle = right
+        .cross(level_right)
+        .dot(forward)
+        .atan2(right.dot(level_right));
+    (Quat::from_axis_angle(forward, angle * t) * orientation).normalize()
+}
+
+/// Pushes the sub around with its ballast, propeller and control surfaces
+fn submarine_forces(
+    mut query: Query<(
+        &SubmarineParams,
+        &mut Submarine,
+        &mut EngineTelemetry,
+        &Transform,
+        &Velocity,
+        &mut ExternalForce,
+        &Controlled,
+    )>,
+    input: Res<CalculatedInput>,
+    settings: Res<ControlSettings>,
+    water: Res<Water>,
+    rapier: Res<RapierConfiguration>,
+    time: Res<Time>,
+) {
+    let delta = time.delta_seconds();
+    for (params, mut sub, mut telemetry, transform, velocity, mut external, controlled) in
+        query.iter_mut()
+    {
+        sub.ballast = (sub.ballast + input.ballast * params.ballast_rate * delta).clamp(0.0, 1.0);
+        sub.throttle = match settings.throttle {
+            ThrottleMode::Analog => input.forward,
+            ThrottleMode::Stepped => {
+                (sub.throttle + input.throttle_steps * settings.throttle_step).clamp(-1.0, 1.0)
+            }
+        };
+        sub.thrust.x = input.strafe;
+        sub.thrust.y = input.ascend;
+        params.ramp_thrust(&mut sub, delta);
+        *telemetry = params.telemetry(&sub);
+
+        let body = BodyState {
+            rotation: transform.rotation,
+            linear_velocity: velocity.linvel,
+            angular_velocity: velocity.angvel,
+        };
+        let flow = water.flow(transform.translation, time.elapsed_seconds());
+        let (force, torque) = params.forces(&sub, &body, flow, water.density, rapier.gravity);
+        external.force = force;
+        external.torque = torque + params.steering(&body, controlled.orientation);
+    }
+}
+
+/// Keeps the sub still while the terrain around it loads
+fn hold_submarine(mut query: Query<(&mut Velocity, &mut ExternalForce), With<Submarine>>) {
+    for (mut velocity, mut external) in query.iter_mut() {
+        *velocity = Velocity::zero();
+        *external = ExternalForce::default();
+    }
+}
+
+fn drill(
+    actions: Res<ActionState>,
+    query: Query<&GlobalTransform, With<Controlled>>,
+    mut edits: EventWriter<TerrainEdit>,
+    time: Res<Time>,
+    mut cooldown: Local<f32>,
+) {
+    *cooldown -= time.delta_seconds();
+    // Drill round tunnels, deposit blocks
+    let drilling = actions.pressed(Action::Drill);
+    let (mode, shape) = match (drilling, actions.pressed(Action::Deposit)) {
+        (true, false) => (
+            EditMode::Subtract,
+            EditShape::Sphere {
+                radius: DRILL_RADIUS,
+            },
+        ),
+        (false, true) => (
+            EditMode::Add,
+            EditShape::Box {
+                half_extents: Vec3::splat(DRILL_RADIUS),
+            },
+        ),
+        _ => return,
+    };
+    if *cooldown > 0.0 {
+        return;
+    }
+    *cooldown = DRILL_INTERVAL;
+
+    for transform in query.iter() {
+        edits.send(TerrainEdit {
+            center: transform.translation() + transform.forward() * DRILL_DISTANCE,
+            shape,
+            mode,
+            strength: DRILL_STRENGTH,
+        });
+    }
+}
+
+#[allow(clippy::type_complexity)]
+fn register_propeller(
+    query: Query<(Entity, &Name), (Without<Propeller>, Added<Name>)>,
+    mut commands: Commands,
+) {
+    for (entity, name) in query.iter() {
+        if name.as_str() == "Propeller" {
+            commands.entity(entity).insert(Propeller);
+            info!("Registered propeller");
+        }
+    }
+}
+
+fn rotate_propeller(
+    mut query: Query<&mut Transform, With<Propeller>>,
+    engines: Query<&EngineTelemetry>,
+    time: Res<Time>,
+) {
+    let rpm = engines.get_single().map_or(0.0, |telemetry| telemetry.rpm);
+    for mut transform in query.iter_mut() {
+        transform.rotate_x(time.delta_seconds() * 2.0 * PI * rpm / 60.0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn level_keeps_heading() {
+        let heading = Quat::from_rotation_y(1.0) * Quat::from_rotation_x(0.4);
+        let rolled = heading * Quat::from_rotation_z(0.7);
+        let leveled = level(rolled, 1.0);
+        assert!(leveled.angle_between(heading) < 1.0e-3);
+        // Halfway levels half the roll
+        let half = level(rolled, 0.5);
+        let expected = heading * Quat::from_rotation_z(0.35);
+        assert!(half.angle_between(expected) < 1.0e-3);
+        // Upside down rolls back upright
+        let flipped = heading * Quat::from_rotation_z(3.0);
+        assert!(level(flipped, 1.0).angle_between(heading) < 1.0e-3);
+    }
+}
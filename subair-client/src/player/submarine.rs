@@ -0,0 +1,390 @@
+use bevy::prelude::*;
+use bevy_rapier3d::prelude::*;
+
+use crate::world::density::{DensityFunction, NoiseLayer, NoiseSettings};
+
+// Keeps the turbulence from lining up with terrain noise using the same seed
+const TURBULENCE_SEED: u64 = 0xC0_55E7;
+
+/// Tuning of the submarine, every value is in SI units
+#[derive(Debug, Clone, Component, Reflect)]
+pub struct SubmarineParams {
+    /// Mass of the hull without ballast water
+    pub mass: f32,
+    /// Mass of the water in full ballast tanks
+    pub ballast_capacity: f32,
+    /// Fraction of the ballast tanks filled or emptied per second
+    pub ballast_rate: f32,
+    /// Volume of water the hull displaces
+    pub displacement: f32,
+    /// Where buoyancy pushes, relative to the center of mass. Above it, the
+    /// sub rights itself
+    pub buoyancy_offset: Vec3,
+    /// Drag proportional to the speed through the water, along the sub's own axes
+    pub linear_drag: Vec3,
+    /// Drag proportional to the squared speed, along the sub's own axes
+    pub quadratic_drag: Vec3,
+    /// Drag torque proportional to the rotation speed
+    pub angular_drag: f32,
+    /// Force of the propeller at full power
+    pub max_thrust: f32,
+    /// Force of the thrusters that push the sub sideways, up and down
+    pub lateral_thrust: f32,
+    /// How quickly the propeller follows the throttle, in full throttle per second
+    pub throttle_ramp: f32,
+    /// Propeller speed at full thrust, in rotations per minute
+    pub max_rpm: f32,
+    /// Power the propeller draws at full thrust, in watts
+    pub max_power: f32,
+    /// Power every thruster draws at full thrust, in watts
+    pub thruster_power: f32,
+    /// Torque per radian between the sub's rotation and the controlled one
+    pub steering_stiffness: f32,
+    /// Torque per radian per second of rotation, keeps the steering from overshooting
+    pub steering_damping: f32,
+    /// Strongest torque the control surfaces can apply
+    pub max_steering_torque: f32,
+}
+
+impl Default for SubmarineParams {
+    fn default() -> Self {
+        Self {
+            mass: 2000.0,
+            ballast_capacity: 500.0,
+            ballast_rate: 0.2,
+            displacement: 2.2,
+            buoyancy_offset: Vec3::new(0.0, 0.05, 0.0),
+            linear_drag: Vec3::new(200.0, 200.0, 50.0),
+            quadratic_drag: Vec3::new(60.0, 60.0, 15.0),
+            angular_drag: 500.0,
+            max_thrust: 6000.0,
+            lateral_thrust: 2000.0,
+            throttle_ramp: 0.5,
+            max_rpm: 300.0,
+            max_power: 40000.0,
+            thruster_power: 8000.0,
+            steering_stiffness: 4000.0,
+            steering_damping: 3000.0,
+            max_steering_torque: 3000.0,
+        }
+    }
+}
+
+/// State of the submarine's systems
+#[derive(Debug, Clone, Copy, Component, Reflect, Default)]
+pub struct Submarine {
+    /// Fill level of the ballast tanks, from 0 when empty to 1 when full
+    pub ballast: f32,
+    /// Thrust to the right, up and ahead, from -1 to 1 on every axis. Ahead
+    /// is the propeller, the other axes are the thrusters
+    pub thrust: Vec3,
+    /// Propeller thrust the engine works towards, from -1 to 1
+    pub throttle: f32,
+}
+
+/// What the engine is doing, for the HUD and the inspector
+#[derive(Debug, Clone, Copy, Component, Reflect, Default)]
+pub struct EngineTelemetry {
+    /// Propeller speed in rotations per minute, negative in reverse
+    pub rpm: f32,
+    /// Propeller thrust in newtons
+    pub thrust: f32,
+    /// Power drawn by the propeller and the thrusters in kilowatts
+    pub power_draw: f32,
+}
+
+impl Submarine {
+    /// Ballast at which the sub neither rises nor sinks in `water`
+    pub fn neutral(params: &SubmarineParams, water: &Water) -> Self {
+        let ballast = (water.density * params.displacement - params.mass) / params.ballast_capacity;
+        Self {
+            ballast: ballast.clamp(0.0, 1.0),
+            thrust: Vec3::ZERO,
+            throttle: 0.0,
+        }
+    }
+}
+
+/// The water everything floats in
+#[derive(Resource, Reflect)]
+pub struct Water {
+    /// Density in kg/m³
+    pub density: f32,
+    /// Average flow of the current in m/s
+    pub current: Vec3,
+    /// Largest deviation of the flow from the average current in m/s
+    pub turbulence: f32,
+    /// Size of the eddies in m
+    pub eddy_size: f32,
+    // Deviation of the flow on every axis
+    #[reflect(ignore)]
+    noise: [NoiseLayer; 3],
+}
+
+impl Default for Water {
+    fn default() -> Self {
+        Self::new(1025.0, Vec3::new(0.4, 0.0, 0.2), 0.3, 40.0)
+    }
+}
+
+impl Water {
+    pub fn new(density: f32, current: Vec3, turbulence: f32, eddy_size: f32) -> Self {
+        let settings = NoiseSettings {
+            frequency: 1.0,
+            ..default()
+        };
+        Self {
+            density,
+            current,
+            turbulence,
+            eddy_size,
+            noise: [0, 1, 2].map(|i| NoiseLayer::new(TURBULENCE_SEED + i, settings, 1.0)),
+        }
+    }
+
+    /// Velocity of the water at `position` after `seconds`
+    pub fn flow(&self, position: Vec3, seconds: f32) -> Vec3 {
+        if self.turbulence == 0.0 || self.eddy_size <= 0.0 {
+            return self.current;
+        }
+        // The eddies drift along with the current
+        let point = (position - self.current * seconds) / self.eddy_size;
+        let deviation = Vec3::from(self.noise.each_ref().map(|noise| noise.sample(point)));
+        self.current + deviation * self.turbulence
+    }
+}
+
+/// Motion of a rigid body in world space
+#[derive(Debug, Clone, Copy)]
+pub struct BodyState {
+    pub rotation: Quat,
+    pub linear_velocity: Vec3,
+    pub angular_velocity: Vec3,
+}
+
+impl SubmarineParams {
+    /// Force and torque of gravity, buoyancy, drag and thrust on the sub in
+    /// water flowing at `flow`
+    pub fn forces(
+        &self,
+        sub: &Submarine,
+        body: &BodyState,
+        flow: Vec3,
+        water_density: f32,
+        gravity: Vec3,
+    ) -> (Vec3, Vec3) {
+        // Gravity is applied here rather than by the physics engine, as the ballast changes the mass
+        let weight = gravity * (self.mass + sub.ballast * self.ballast_capacity);
+        let buoyancy = -gravity * water_density * self.displacement;
+
+        // Drag works against the motion through the water, not against the motion over the ground
+        let local_velocity = body.rotation.inverse() * (body.linear_velocity - flow);
+        let local_drag =
+            -(self.linear_drag + self.quadratic_drag * local_velocity.abs()) * local_velocity;
+        let drag = body.rotation * local_drag;
+
+        let local_thrust = sub.thrust * Vec3::new(1.0, 1.0, -1.0);
+        let thrust = body.rotation
+            * (local_thrust * Vec3::new(self.lateral_thrust, self.lateral_thrust, self.max_thrust));
+
+        let righting = (body.rotation * self.buoyancy_offset).cross(buoyancy);
+        let angular_drag = -body.angular_velocity * self.angular_drag;
+        (weight + buoyancy + drag + thrust, righting + angular_drag)
+    }
+
+    /// Moves the propeller thrust towards the throttle over `seconds`
+    pub fn ramp_thrust(&self, sub: &mut Submarine, seconds: f32) {
+        let step = self.throttle_ramp * seconds;
+        let difference = sub.throttle.clamp(-1.0, 1.0) - sub.thrust.z;
+        sub.thrust.z += difference.clamp(-step, step);
+    }
+
+    pub fn telemetry(&self, sub: &Submarine) -> EngineTelemetry {
+        let propeller = sub.thrust.z;
+        // Thrust grows with the square of the propeller speed, power with its cube
+        let speed = propeller.abs().sqrt();
+        let thrusters = sub.thrust.x.abs() + sub.thrust.y.abs();
+        EngineTelemetry {
+            rpm: speed * propeller.signum() * self.max_rpm,
+            thrust: propeller * self.max_thrust,
+            power_draw: (speed.powi(3) * self.max_power + thrusters * self.thruster_power) / 1000.0,
+        }
+    }
+
+    /// Torque that turns the sub towards `target`
+    pub fn steering(&self, body: &BodyState, target: Quat) -> Vec3 {
+        let mut difference = target * body.rotation.inverse();
+        // The shorter way around
+        if difference.w < 0.0 {
+            difference = -difference;
+        }
+        let (axis, angle) = difference.to_axis_angle();
+        let torque =
+            axis * angle * self.steering_stiffness - body.angular_velocity * self.steering_damping;
+        torque.clamp_length_max(self.max_steering_torque)
+    }
+}
+
+/// Physics components of a submarine with `params`, which starts out neutrally buoyant
+pub fn submarine_bundle(params: SubmarineParams, water: &Water) -> impl Bundle {
+    (
+        Submarine::neutral(&params, water),
+        EngineTelemetry::default(),
+        RigidBody::Dynamic,
+        // All of the mass is on the collider, whose shape gives the inertia
+        ColliderMassProperties::Mass(params.mass),
+        // Gravity depends on the ballast, so it is part of the forces
+        GravityScale(0.0),
+        // Fast subs would tunnel through thin terrain
+        Ccd::enabled(),
+        Velocity::default(),
+        ExternalForce::default(),
+        params,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f32::consts::FRAC_PI_2;
+
+    const GRAVITY: Vec3 = Vec3::new(0.0, -9.81, 0.0);
+
+    fn at_rest() -> BodyState {
+        BodyState {
+            rotation: Quat::IDENTITY,
+            linear_velocity: Vec3::ZERO,
+            angular_velocity: Vec3::ZERO,
+        }
+    }
+
+    #[test]
+    fn neutral_ballast_floats() {
+        let params = SubmarineParams::default();
+        let water = Water::default();
+        let sub = Submarine::neutral(&params, &water);
+        assert!(sub.ballast > 0.0 && sub.ballast < 1.0);
+        let (force, torque) = params.forces(&sub, &at_rest(), Vec3::ZERO, water.density, GRAVITY);
+        assert!(force.length() < 1.0e-2, "{force}");
+        assert!(torque.length() < 1.0e-2, "{torque}");
+
+        // Flooding the tanks sinks the sub, blowing them lifts it
+        let full = Submarine {
+            ballast: 1.0,
+            ..sub
+        };
+        let empty = Submarine {
+            ballast: 0.0,
+            ..sub
+        };
+        assert!(
+            params
+                .forces(&full, &at_rest(), Vec3::ZERO, water.density, GRAVITY)
+                .0
+                .y
+                < 0.0
+        );
+        assert!(
+            params
+                .forces(&empty, &at_rest(), Vec3::ZERO, water.density, GRAVITY)
+                .0
+                .y
+                > 0.0
+        );
+    }
+
+    #[test]
+    fn current_carries_the_sub() {
+        let params = SubmarineParams::default();
+        let water = Water::default();
+        let sub = Submarine::neutral(&params, &water);
+        let flow = Vec3::new(1.0, 0.0, 0.0);
+        let (force, _) = params.forces(&sub, &at_rest(), flow, water.density, GRAVITY);
+        assert!(force.x > 0.0 && force.z.abs() < 1.0e-3, "{force}");
+        // Moving along with the water there is no drag
+        let drifting = BodyState {
+            linear_velocity: flow,
+            ..at_rest()
+        };
+        let (force, _) = params.forces(&sub, &drifting, flow, water.density, GRAVITY);
+        assert!(force.length() < 1.0e-2, "{force}");
+    }
+
+    #[test]
+    fn thrust_reaches_top_speed() {
+        let params = SubmarineParams::default();
+        let sub = Submarine {
+            thrust: Vec3::Z,
+            ..Submarine::neutral(&params, &Water::default())
+        };
+        // Forward drag balances the thrust at the top speed
+        let (c, b) = (params.quadratic_drag.z, params.linear_drag.z);
+        let top_speed = (-b + (b * b + 4.0 * c * params.max_thrust).sqrt()) / (2.0 * c);
+        let cruising = BodyState {
+            linear_velocity: Vec3::NEG_Z * top_speed,
+            ..at_rest()
+        };
+        let (force, _) = params.forces(&sub, &cruising, Vec3::ZERO, 1025.0, GRAVITY);
+        assert!(force.length() < 1.0, "{force}");
+
+        // The thrusters push along the sub's own axes
+        let turned = BodyState {
+            rotation: Quat::from_rotation_y(FRAC_PI_2),
+            ..at_rest()
+        };
+        let strafing = Submarine {
+            thrust: Vec3::X,
+            ..sub
+        };
+        let (force, _) = params.forces(&strafing, &turned, Vec3::ZERO, 1025.0, GRAVITY);
+        assert!(
+            (force - Vec3::NEG_Z * params.lateral_thrust).length() < 1.0,
+            "{force}"
+        );
+    }
+
+    #[test]
+    fn throttle_ramps_up() {
+        let params = SubmarineParams::default();
+        let mut sub = Submarine {
+            throttle: 1.0,
+            ..default()
+        };
+        params.ramp_thrust(&mut sub, 0.5);
+        assert_eq!(sub.thrust.z, params.throttle_ramp * 0.5);
+        for _ in 0..100 {
+            params.ramp_thrust(&mut sub, 0.1);
+        }
+        assert_eq!(sub.thrust.z, 1.0);
+        let telemetry = params.telemetry(&sub);
+        assert_eq!(telemetry.rpm, params.max_rpm);
+        assert_eq!(telemetry.thrust, params.max_thrust);
+        assert_eq!(telemetry.power_draw, params.max_power / 1000.0);
+
+        // Reversing runs the propeller backwards
+        sub.throttle = -1.0;
+        params.ramp_thrust(&mut sub, 10.0);
+        assert_eq!(sub.thrust.z, -1.0);
+        assert_eq!(params.telemetry(&sub).rpm, -params.max_rpm);
+        // An idle engine draws nothing
+        let idle = params.telemetry(&Submarine::default());
+        assert_eq!((idle.rpm, idle.power_draw), (0.0, 0.0));
+    }
+
+    #[test]
+    fn steering_turns_the_short_way() {
+        let params = SubmarineParams::default();
+        let target = Quat::from_rotation_y(0.5);
+        let torque = params.steering(&at_rest(), target);
+        assert!(torque.y > 0.0 && torque.x.abs() < 1.0e-3 && torque.z.abs() < 1.0e-3);
+        // Already turning fast enough, the damping brakes
+        let turning = BodyState {
+            angular_velocity: Vec3::Y * 2.0,
+            ..at_rest()
+        };
+        assert!(params.steering(&turning, target).y < 0.0);
+        // Almost half a turn the other way round
+        let behind = Quat::from_rotation_y(-3.0);
+        assert!(params.steering(&at_rest(), behind).y < 0.0);
+    }
+}
@@ -0,0 +1,237 @@
+use super::density::{BoxedDensity, DensityFunction, NoiseLayer, NoiseSettings};
+use super::material::TerrainMaterial;
+use super::preset::DensityNode;
+use crate::player::Controlled;
+use bevy::prelude::*;
+use serde::Deserialize;
+use std::sync::Arc;
+use tracing::debug;
+
+// Perlin noise mostly stays within this range, so it is stretched to reach every biome
+const BIOME_NOISE_RANGE: f32 = 0.35;
+// How quickly the fog follows the biome at the player, per second
+const FOG_BLEND_RATE: f32 = 1.5;
+// Keeps the biome noise from lining up with terrain noise using the same settings
+const BIOME_SEED_OFFSET: u64 = 0x5EED_B10E;
+
+/// Serializable description of a biome, part of the world preset
+#[derive(Debug, Clone, Deserialize)]
+pub struct BiomeDefinition {
+    pub name: String,
+    /// Added to the world density, weighted by how much the biome is present
+    #[serde(default)]
+    pub density: Option<DensityNode>,
+    /// Tint of the terrain layer colors
+    pub color: [f32; 3],
+    pub fog_color: [f32; 3],
+    pub fog_extinction_color: [f32; 3],
+    /// Distance at which the fog hides everything
+    pub fog_visibility: f32,
+}
+
+pub struct Biome {
+    pub name: String,
+    density: Option<BoxedDensity>,
+    pub color: Color,
+    pub fog: BiomeFog,
+}
+
+/// Biomes along a low frequency noise, sampled in chunk space
+pub struct BiomeMap {
+    noise: NoiseLayer,
+    chunk_size: f32,
+    /// How abruptly neighbouring biomes change into each other, 1 blends across the whole biome
+    sharpness: f32,
+    biomes: Vec<Biome>,
+}
+
+impl BiomeMap {
+    pub fn new(
+        seed: u64,
+        settings: NoiseSettings,
+        sharpness: f32,
+        chunk_size: usize,
+        definitions: &[BiomeDefinition],
+    ) -> Self {
+        let biomes = definitions
+            .iter()
+            .map(|definition| Biome {
+                name: definition.name.clone(),
+                density: definition.density.as_ref().map(|node| node.build(seed)),
+                color: rgb(definition.color),
+                fog: BiomeFog {
+                    color: rgb(definition.fog_color),
+                    extinction_color: rgb(definition.fog_extinction_color),
+                    visibility: definition.fog_visibility,
+                },
+            })
+            .collect();
+        Self {
+            noise: NoiseLayer::new(
+                seed.wrapping_add(BIOME_SEED_OFFSET),
+                settings,
+                1.0 / BIOME_NOISE_RANGE,
+            ),
+            chunk_size: chunk_size as f32,
+            sharpness: sharpness.max(1.0),
+            biomes,
+        }
+    }
+
+    pub fn biomes(&self) -> &[Biome] {
+        &self.biomes
+    }
+
+    /// The two biomes closest to the noise value at `position` and their weights, which sum to 1
+    pub fn weights(&self, position: Vec3) -> [(usize, f32); 2] {
+        let last = self.biomes.len().saturating_sub(1);
+        let value = self
+            .noise
+            .sample(position / self.chunk_size)
+            .clamp(-1.0, 1.0);
+        // Biomes are spread evenly over the noise range, centered in their section
+        let x = ((value + 1.0) * 0.5 * self.biomes.len() as f32 - 0.5).clamp(0.0, last as f32);
+        let index = (x.floor() as usize).min(last);
+        let t = ((x - index as f32 - 0.5) * self.sharpness + 0.5).clamp(0.0, 1.0);
+        [(index, 1.0 - t), ((index + 1).min(last), t)]
+    }
+
+    pub fn dominant(&self, position: Vec3) -> usize {
+        let [(a, weight_a), (b, weight_b)] = self.weights(position);
+        if weight_a >= weight_b {
+            a
+        } else {
+            b
+        }
+    }
+}
+
+/// World density with the contribution of each biome added
+pub struct BiomeDensity {
+    pub base: BoxedDensity,
+    pub biomes: Arc<BiomeMap>,
+}
+
+impl DensityFunction for BiomeDensity {
+    fn sample(&self, point: Vec3) -> f32 {
+        let mut density = self.base.sample(point);
+        for (index, weight) in self.biomes.weights(point) {
+            if weight <= 0.0 {
+                continue;
+            }
+            if let Some(biome) = &self.biomes.biomes[index].density {
+                density += biome.sample(point) * weight;
+            }
+        }
+        density
+    }
+
+    fn sample_batch(&self, points: &[Vec3], out: &mut [f32]) {
+        self.base.sample_batch(points, out);
+        let weights: Vec<_> = points
+            .iter()
+            .map(|point| self.biomes.weights(*point))
+            .collect();
+        // Every biome density only samples the points it contributes to
+        let mut selected = vec![];
+        let mut values = vec![];
+        for (biome_index, biome) in self.biomes.biomes.iter().enumerate() {
+            let Some(density) = &biome.density else { continue };
+            selected.clear();
+            selected.extend(weights.iter().enumerate().flat_map(|(i, pair)| {
+                pair.iter()
+                    .filter(move |(index, weight)| *index == biome_index && *weight > 0.0)
+                    .map(move |(_, weight)| (i, *weight))
+            }));
+            let selected_points: Vec<_> = selected.iter().map(|(i, _)| points[*i]).collect();
+            values.resize(selected_points.len(), 0.0);
+            density.sample_batch(&selected_points, &mut values);
+            for ((i, weight), value) in selected.iter().zip(&values) {
+                out[*i] += value * weight;
+            }
+        }
+    }
+
+    fn bounds(&self, min: Vec3, max: Vec3) -> (f32, f32) {
+        // Biome weights are between 0 and 1, so a biome can add anything up to its full density
+        let biomes = self
+            .biomes
+            .biomes
+            .iter()
+            .filter_map(|biome| biome.density.as_ref());
+        biomes.fold(self.base.bounds(min, max), |(low, high), density| {
+            let (a, b) = density.bounds(min, max);
+            (low + a.min(0.0), high + b.max(0.0))
+        })
+    }
+}
+
+#[derive(Resource, Clone)]
+pub struct WorldBiomes(pub Arc<BiomeMap>);
+
+/// Terrain material of every biome, in the same order
+#[derive(Debug, Resource)]
+pub struct BiomeMaterials(pub Vec<Handle<TerrainMaterial>>);
+
+/// Fog around the player, blended between the surrounding biomes
+#[derive(Debug, Clone, Copy, Resource, Reflect)]
+pub struct BiomeFog {
+    pub color: Color,
+    pub extinction_color: Color,
+    pub visibility: f32,
+}
+
+impl BiomeFog {
+    fn lerp(&self, other: &BiomeFog, t: f32) -> BiomeFog {
+        let lerp_color = |a: Color, b: Color| Color::from(Vec4::from(a).lerp(Vec4::from(b), t));
+        BiomeFog {
+            color: lerp_color(self.color, other.color),
+            extinction_color: lerp_color(self.extinction_color, other.extinction_color),
+            visibility: self.visibility + (other.visibility - self.visibility) * t,
+        }
+    }
+}
+
+pub fn blend_biome_fog(
+    mut commands: Commands,
+    biomes: Res<WorldBiomes>,
+    current: Option<ResMut<BiomeFog>>,
+    time: Res<Time>,
+    player: Query<&GlobalTransform, With<Controlled>>,
+    mut fogs: Query<&mut FogSettings>,
+    mut last_biome: Local<Option<usize>>,
+) {
+    let Ok(transform) = player.get_single() else { return };
+    let position = transform.translation();
+    let [(a, _), (b, weight)] = biomes.0.weights(position);
+    let target = biomes.0.biomes[a].fog.lerp(&biomes.0.biomes[b].fog, weight);
+
+    let dominant = biomes.0.dominant(position);
+    if *last_biome != Some(dominant) {
+        *last_biome = Some(dominant);
+        debug!(
+            biome = biomes.0.biomes[dominant].name.as_str(),
+            "Entered biome"
+        );
+    }
+
+    let fog = match current {
+        Some(mut current) => {
+            let t = 1.0 - (-FOG_BLEND_RATE * time.delta_seconds()).exp();
+            *current = current.lerp(&target, t);
+            *current
+        }
+        None => {
+            commands.insert_resource(target);
+            target
+        }
+    };
+    for mut settings in fogs.iter_mut() {
+        settings.color = fog.color;
+        settings.falloff = FogFalloff::from_visibility_color(fog.visibility, fog.extinction_color);
+    }
+}
+
+fn rgb([red, green, blue]: [f32; 3]) -> Color {
+    Color::rgb(red, green, blue)
+}
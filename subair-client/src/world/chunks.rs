@@ -0,0 +1,182 @@
+use super::edit::{Neighbourhood, TerrainEdits};
+use super::generate::{self, ChunkContent};
+use super::queue::GenerationQueue;
+use super::{WorldDensity, WorldInfo, WorldMeshTask};
+use crate::player::Controlled;
+use bevy::{prelude::*, tasks::AsyncComputeTaskPool, utils::HashMap};
+use tracing::debug;
+
+// Chunk sizes must be divisible by the stride of the last LOD
+pub const MAX_LOD: u32 = 3;
+
+#[derive(Debug, Resource, Reflect)]
+pub struct ChunkSettings {
+    /// Radius in chunks around the controlled entity that is kept loaded
+    pub view_radius: i32,
+    /// Chunk distances after which the next LOD is used
+    pub lod_distances: Vec<i32>,
+    /// Radius in chunks around moving bodies in which chunks get colliders
+    pub physics_radius: i32,
+    /// LOD colliders are built at, higher values decimate them
+    pub collider_lod: u32,
+    /// Generation tasks that may run at the same time, the rest wait in a queue
+    pub max_generation_tasks: usize,
+}
+
+impl ChunkSettings {
+    fn lod(&self, relative: IVec3) -> u32 {
+        let distance_squared = relative.dot(relative);
+        let lod = self
+            .lod_distances
+            .iter()
+            .filter(|distance| distance_squared > *distance * *distance)
+            .count() as u32;
+        lod.min(MAX_LOD)
+    }
+}
+
+#[derive(Debug, Component, Reflect, Clone, Copy)]
+pub struct Chunk(pub IVec3);
+
+#[derive(Debug, Resource, Default)]
+pub struct LoadedChunks {
+    chunks: HashMap<IVec3, (Entity, u32)>,
+    // Center and radius used for the last update
+    last_update: Option<(IVec3, i32)>,
+}
+
+impl LoadedChunks {
+    pub fn get(&self, coord: IVec3) -> Option<(Entity, u32)> {
+        self.chunks.get(&coord).copied()
+    }
+
+    /// Despawns every chunk so they are generated again
+    pub fn unload_all(&mut self, commands: &mut Commands, queue: &mut GenerationQueue) {
+        queue.clear();
+        for (entity, _) in self.chunks.drain().map(|(_, chunk)| chunk) {
+            commands.entity(entity).despawn_recursive();
+        }
+        self.last_update = None;
+    }
+}
+
+/// Whether the surface may cross the chunk, judged by the bounds of the density
+pub fn has_surface(
+    info: &WorldInfo,
+    density: &WorldDensity,
+    coord: IVec3,
+    edits: &Neighbourhood,
+) -> bool {
+    // Edits can carve a surface into any chunk around them
+    edits.iter().any(Option::is_some)
+        || generate::classify_chunk(
+            density.0.as_ref(),
+            info.chunk_offset(coord),
+            info.chunk_size,
+        ) == ChunkContent::Mixed
+}
+
+pub fn generation_task(
+    info: &WorldInfo,
+    density: &WorldDensity,
+    coord: IVec3,
+    lod: u32,
+    edits: Neighbourhood,
+) -> WorldMeshTask {
+    let offset = info.chunk_offset(coord);
+    let (density, seed, normal_mode, welding, size) = (
+        density.0.clone(),
+        info.seed,
+        info.normal_mode,
+        info.welding,
+        info.chunk_size,
+    );
+    let task = AsyncComputeTaskPool::get().spawn(async move {
+        generate::generate_world(
+            density,
+            seed,
+            normal_mode,
+            welding,
+            offset,
+            size,
+            lod,
+            edits,
+        )
+    });
+    WorldMeshTask(task)
+}
+
+pub fn in_view(relative: IVec3, radius: i32) -> bool {
+    relative.dot(relative) <= radius * radius
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn update_loaded_chunks(
+    mut commands: Commands,
+    info: Res<WorldInfo>,
+    density: Res<WorldDensity>,
+    settings: Res<ChunkSettings>,
+    edits: Res<TerrainEdits>,
+    mut loaded: ResMut<LoadedChunks>,
+    mut queue: ResMut<GenerationQueue>,
+    player: Query<&GlobalTransform, With<Controlled>>,
+) {
+    let Ok(transform) = player.get_single() else { return };
+    let center = info.chunk_coord(transform.translation());
+    let radius = settings.view_radius;
+    if loaded.last_update == Some((center, radius)) {
+        return;
+    }
+    loaded.last_update = Some((center, radius));
+
+    let mut unloaded = 0;
+    loaded.chunks.retain(|coord, (entity, _)| {
+        if in_view(*coord - center, radius) {
+            return true;
+        }
+        // Dropping the task cancels it
+        queue.cancel(*coord, *entity);
+        commands.entity(*entity).despawn_recursive();
+        unloaded += 1;
+        false
+    });
+
+    let mut scheduled = 0;
+    let mut remeshed = 0;
+    let mut skipped = 0;
+    for x in -radius..=radius {
+        for y in -radius..=radius {
+            for z in -radius..=radius {
+                let relative = IVec3::new(x, y, z);
+                if !in_view(relative, radius) {
+                    continue;
+                }
+                let coord = center + relative;
+                let lod = settings.lod(relative);
+                let entity = match loaded.chunks.get(&coord) {
+                    Some((_, loaded_lod)) if *loaded_lod == lod => continue,
+                    // The old mesh stays visible until the new one is done
+                    Some((entity, _)) => {
+                        remeshed += 1;
+                        *entity
+                    }
+                    None => commands.spawn(Chunk(coord)).id(),
+                };
+                loaded.chunks.insert(coord, (entity, lod));
+
+                if !has_surface(&info, &density, coord, &edits.neighbourhood(coord)) {
+                    // Still loaded, so it isn't classified again every update
+                    skipped += 1;
+                    continue;
+                }
+                queue.request(&mut commands, coord, entity, lod);
+                scheduled += 1;
+            }
+        }
+    }
+
+    debug!(
+        ?center,
+        scheduled, remeshed, skipped, unloaded, "Updated loaded chunks"
+    );
+}
@@ -0,0 +1,499 @@
+use super::perlin::BatchedPerlin;
+use bevy::prelude::*;
+use bracket_noise::prelude::*;
+use serde::Deserialize;
+
+/// Density of the terrain at a point in world space, positive values are solid
+pub trait DensityFunction: Send + Sync {
+    fn sample(&self, point: Vec3) -> f32;
+
+    /// Samples every point into `out`, which has the same length. Gives the
+    /// same values as `sample`, but only dispatches once per node of the tree
+    fn sample_batch(&self, points: &[Vec3], out: &mut [f32]) {
+        for (point, out) in points.iter().zip(out) {
+            *out = self.sample(*point);
+        }
+    }
+
+    /// Lower and upper bound of the density inside the box from `min` to
+    /// `max`. Bounds only have to contain every value, so the default is unbounded
+    fn bounds(&self, _min: Vec3, _max: Vec3) -> (f32, f32) {
+        (f32::NEG_INFINITY, f32::INFINITY)
+    }
+}
+
+pub type BoxedDensity = Box<dyn DensityFunction>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct NoiseSettings {
+    #[serde(with = "NoiseTypeDef")]
+    pub noise_type: NoiseType,
+    #[serde(with = "FractalTypeDef")]
+    pub fractal_type: FractalType,
+    pub octaves: i32,
+    pub gain: f32,
+    pub lacunarity: f32,
+    pub frequency: f32,
+}
+
+impl Default for NoiseSettings {
+    fn default() -> Self {
+        Self {
+            noise_type: NoiseType::PerlinFractal,
+            fractal_type: FractalType::FBM,
+            octaves: 1,
+            gain: 0.6,
+            lacunarity: 2.0,
+            frequency: 0.05,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(remote = "NoiseType")]
+enum NoiseTypeDef {
+    Value,
+    ValueFractal,
+    Perlin,
+    PerlinFractal,
+    Simplex,
+    SimplexFractal,
+    Cellular,
+    WhiteNoise,
+    Cubic,
+    CubicFractal,
+}
+
+// Variant names have to match bracket-noise
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Deserialize)]
+#[serde(remote = "FractalType")]
+enum FractalTypeDef {
+    FBM,
+    Billow,
+    RigidMulti,
+}
+
+impl NoiseSettings {
+    pub fn build(&self, seed: u64) -> FastNoise {
+        let mut noise = FastNoise::seeded(seed);
+        noise.set_noise_type(self.noise_type);
+        noise.set_fractal_type(self.fractal_type);
+        noise.set_fractal_octaves(self.octaves);
+        noise.set_fractal_gain(self.gain);
+        noise.set_fractal_lacunarity(self.lacunarity);
+        noise.set_frequency(self.frequency);
+        noise
+    }
+
+    /// Largest absolute value and gradient length of the noise, `None` for
+    /// noise types without a proven bound, which are never skipped
+    fn limits(&self) -> Option<(f32, f32)> {
+        // Derived at a frequency of 1 from bracket-noise's default quintic
+        // interpolation, whose slope is at most 30/16. The corner values are
+        // mixed with weights from 0 to 1, so the noise stays within the largest
+        // corner value. Along an axis it changes by at most that slope times
+        // the difference of two corners, plus the slope of the corners themselves
+        const SLOPE: f32 = 30.0 / 16.0;
+        let (fractal, value, axis_gradient) = match self.noise_type {
+            // Corners are values of VAL_LUT, all within -1..1
+            NoiseType::Value => (false, 1.0, SLOPE * 2.0),
+            NoiseType::ValueFractal => (true, 1.0, SLOPE * 2.0),
+            // Corners are offsets within -1..1 dotted with one of 12 gradients
+            // with two components of ±1 each, so they are within -2..2
+            NoiseType::Perlin => (false, 2.0, SLOPE * 4.0 + 1.0),
+            NoiseType::PerlinFractal => (true, 2.0, SLOPE * 4.0 + 1.0),
+            _ => return None,
+        };
+        let gradient = axis_gradient * 3f32.sqrt() * self.frequency.abs();
+        if !fractal {
+            return Some((value, gradient));
+        }
+        // Octave values and their gradients, following bracket-noise
+        let (value, gradient, bounded) = match self.fractal_type {
+            FractalType::FBM => (value, gradient, true),
+            FractalType::Billow => ((value * 2.0 - 1.0).max(1.0), gradient * 2.0, true),
+            FractalType::RigidMulti => ((value - 1.0).max(1.0), gradient, false),
+        };
+        let (mut amplitude, mut scale) = (1.0, 1.0);
+        let (mut value_sum, mut gradient_sum) = (0.0, 0.0);
+        for _ in 0..self.octaves.max(1) {
+            value_sum += amplitude * value;
+            gradient_sum += amplitude * scale * gradient;
+            amplitude *= self.gain.abs();
+            scale *= self.lacunarity.abs();
+        }
+        let bounding = if bounded {
+            1.0 / (0..self.octaves)
+                .map(|i| self.gain.powi(i + 1))
+                .fold(1.0, |sum, amplitude| sum + amplitude)
+        } else {
+            1.0
+        };
+        Some((value_sum * bounding.abs(), gradient_sum * bounding.abs()))
+    }
+}
+
+/// Noise scaled to `-amplitude..amplitude`
+pub struct NoiseLayer {
+    noise: FastNoise,
+    // Same noise for whole batches of points, when the noise type supports it
+    batched: Option<BatchedPerlin>,
+    amplitude: f32,
+    // Largest absolute value and gradient length, already scaled by the amplitude
+    limits: Option<(f32, f32)>,
+}
+
+impl NoiseLayer {
+    pub fn new(seed: u64, settings: NoiseSettings, amplitude: f32) -> Self {
+        Self {
+            noise: settings.build(seed),
+            batched: BatchedPerlin::new(seed, settings),
+            amplitude,
+            limits: settings
+                .limits()
+                .map(|(value, gradient)| (value * amplitude.abs(), gradient * amplitude.abs())),
+        }
+    }
+
+    /// Largest absolute value of the noise
+    pub fn max_value(&self) -> f32 {
+        self.limits.map_or(f32::INFINITY, |(value, _)| value)
+    }
+}
+
+impl DensityFunction for NoiseLayer {
+    fn sample(&self, point: Vec3) -> f32 {
+        self.noise.get_noise3d(point.x, point.y, point.z) * self.amplitude
+    }
+
+    fn sample_batch(&self, points: &[Vec3], out: &mut [f32]) {
+        match &self.batched {
+            Some(batched) => batched.sample_batch(points, self.amplitude, out),
+            None => {
+                for (point, out) in points.iter().zip(out) {
+                    *out = self.sample(*point);
+                }
+            }
+        }
+    }
+
+    fn bounds(&self, min: Vec3, max: Vec3) -> (f32, f32) {
+        let Some((value, gradient)) = self.limits else {
+            return (f32::NEG_INFINITY, f32::INFINITY);
+        };
+        // The noise can't change faster than its gradient away from the center
+        let center = self.sample((min + max) * 0.5);
+        let change = gradient * (max - min).length() * 0.5;
+        ((center - change).max(-value), (center + change).min(value))
+    }
+}
+
+/// Solid sphere
+pub struct Sphere {
+    pub center: Vec3,
+    pub radius: f32,
+}
+
+impl DensityFunction for Sphere {
+    fn sample(&self, point: Vec3) -> f32 {
+        self.radius - point.distance(self.center)
+    }
+
+    fn bounds(&self, min: Vec3, max: Vec3) -> (f32, f32) {
+        let nearest = self.center.clamp(min, max).distance(self.center);
+        let farthest = (self.center - min)
+            .abs()
+            .max((max - self.center).abs())
+            .length();
+        (self.radius - farthest, self.radius - nearest)
+    }
+}
+
+/// Solid box
+pub struct Cuboid {
+    pub center: Vec3,
+    pub half_extents: Vec3,
+}
+
+impl DensityFunction for Cuboid {
+    fn sample(&self, point: Vec3) -> f32 {
+        let q = (point - self.center).abs() - self.half_extents;
+        let distance = q.max(Vec3::ZERO).length() + q.max_element().min(0.0);
+        -distance
+    }
+
+    fn bounds(&self, min: Vec3, max: Vec3) -> (f32, f32) {
+        // Distances change at most as fast as the point moves
+        let center = self.sample((min + max) * 0.5);
+        let change = (max - min).length() * 0.5;
+        (center - change, center + change)
+    }
+}
+
+/// Solid below `height`, getting denser by `gradient` per unit of depth
+pub struct HeightBias {
+    pub height: f32,
+    pub gradient: f32,
+}
+
+impl DensityFunction for HeightBias {
+    fn sample(&self, point: Vec3) -> f32 {
+        (self.height - point.y) * self.gradient
+    }
+
+    fn bounds(&self, min: Vec3, max: Vec3) -> (f32, f32) {
+        let (a, b) = (self.sample(min), self.sample(max));
+        (a.min(b), a.max(b))
+    }
+}
+
+pub struct Constant(pub f32);
+
+impl DensityFunction for Constant {
+    fn sample(&self, _point: Vec3) -> f32 {
+        self.0
+    }
+
+    fn bounds(&self, _min: Vec3, _max: Vec3) -> (f32, f32) {
+        (self.0, self.0)
+    }
+}
+
+/// Offsets the sampled point by noise before sampling `inner`
+pub struct DomainWarp {
+    pub warp: [NoiseLayer; 3],
+    pub inner: BoxedDensity,
+}
+
+impl DomainWarp {
+    pub fn new(seed: u64, settings: NoiseSettings, amplitude: f32, inner: BoxedDensity) -> Self {
+        // Every axis needs its own noise or the point only moves diagonally
+        let warp = [0, 1, 2].map(|i| NoiseLayer::new(seed.wrapping_add(i), settings, amplitude));
+        Self { warp, inner }
+    }
+}
+
+impl DensityFunction for DomainWarp {
+    fn sample(&self, point: Vec3) -> f32 {
+        let offset = Vec3::new(
+            self.warp[0].sample(point),
+            self.warp[1].sample(point),
+            self.warp[2].sample(point),
+        );
+        self.inner.sample(point + offset)
+    }
+
+    fn sample_batch(&self, points: &[Vec3], out: &mut [f32]) {
+        let mut warped = points.to_vec();
+        for (axis, warp) in self.warp.iter().enumerate() {
+            warp.sample_batch(points, out);
+            for (point, offset) in warped.iter_mut().zip(out.iter()) {
+                point[axis] += offset;
+            }
+        }
+        self.inner.sample_batch(&warped, out);
+    }
+
+    fn bounds(&self, min: Vec3, max: Vec3) -> (f32, f32) {
+        // Every point can be moved as far as the warp reaches
+        let reach = Vec3::from(self.warp.each_ref().map(NoiseLayer::max_value));
+        if !reach.is_finite() {
+            return (f32::NEG_INFINITY, f32::INFINITY);
+        }
+        self.inner.bounds(min - reach, max + reach)
+    }
+}
+
+pub struct Add(pub Vec<BoxedDensity>);
+
+impl DensityFunction for Add {
+    fn sample(&self, point: Vec3) -> f32 {
+        self.0.iter().map(|density| density.sample(point)).sum()
+    }
+
+    fn sample_batch(&self, points: &[Vec3], out: &mut [f32]) {
+        out.fill(0.0);
+        let mut values = vec![0.0; points.len()];
+        for density in &self.0 {
+            density.sample_batch(points, &mut values);
+            for (out, value) in out.iter_mut().zip(&values) {
+                *out += value;
+            }
+        }
+    }
+
+    fn bounds(&self, min: Vec3, max: Vec3) -> (f32, f32) {
+        self.0.iter().fold((0.0, 0.0), |(low, high), density| {
+            let (a, b) = density.bounds(min, max);
+            (low + a, high + b)
+        })
+    }
+}
+
+/// Intersection of the solids
+pub struct Min(pub BoxedDensity, pub BoxedDensity);
+
+impl DensityFunction for Min {
+    fn sample(&self, point: Vec3) -> f32 {
+        self.0.sample(point).min(self.1.sample(point))
+    }
+
+    fn sample_batch(&self, points: &[Vec3], out: &mut [f32]) {
+        combine_batch(&self.0, &self.1, points, out, f32::min);
+    }
+
+    fn bounds(&self, min: Vec3, max: Vec3) -> (f32, f32) {
+        let (a, b) = (self.0.bounds(min, max), self.1.bounds(min, max));
+        (a.0.min(b.0), a.1.min(b.1))
+    }
+}
+
+/// Union of the solids
+pub struct Max(pub BoxedDensity, pub BoxedDensity);
+
+impl DensityFunction for Max {
+    fn sample(&self, point: Vec3) -> f32 {
+        self.0.sample(point).max(self.1.sample(point))
+    }
+
+    fn sample_batch(&self, points: &[Vec3], out: &mut [f32]) {
+        combine_batch(&self.0, &self.1, points, out, f32::max);
+    }
+
+    fn bounds(&self, min: Vec3, max: Vec3) -> (f32, f32) {
+        let (a, b) = (self.0.bounds(min, max), self.1.bounds(min, max));
+        (a.0.max(b.0), a.1.max(b.1))
+    }
+}
+
+/// Turns solid into water and water into solid, e.g. to carve caves with `Min`
+pub struct Negate(pub BoxedDensity);
+
+impl DensityFunction for Negate {
+    fn sample(&self, point: Vec3) -> f32 {
+        -self.0.sample(point)
+    }
+
+    fn sample_batch(&self, points: &[Vec3], out: &mut [f32]) {
+        self.0.sample_batch(points, out);
+        for out in out {
+            *out = -*out;
+        }
+    }
+
+    fn bounds(&self, min: Vec3, max: Vec3) -> (f32, f32) {
+        let (low, high) = self.0.bounds(min, max);
+        (-high, -low)
+    }
+}
+
+/// Union of the solids, blended over a distance of `smoothness`
+pub struct SmoothUnion {
+    pub a: BoxedDensity,
+    pub b: BoxedDensity,
+    pub smoothness: f32,
+}
+
+impl SmoothUnion {
+    fn blend(&self, a: f32, b: f32) -> f32 {
+        if self.smoothness <= 0.0 {
+            return a.max(b);
+        }
+        // Polynomial smooth maximum
+        let h = (0.5 + 0.5 * (a - b) / self.smoothness).clamp(0.0, 1.0);
+        b + (a - b) * h + self.smoothness * h * (1.0 - h)
+    }
+}
+
+impl DensityFunction for SmoothUnion {
+    fn sample(&self, point: Vec3) -> f32 {
+        self.blend(self.a.sample(point), self.b.sample(point))
+    }
+
+    fn sample_batch(&self, points: &[Vec3], out: &mut [f32]) {
+        combine_batch(&self.a, &self.b, points, out, |a, b| self.blend(a, b));
+    }
+
+    fn bounds(&self, min: Vec3, max: Vec3) -> (f32, f32) {
+        let (a, b) = (self.a.bounds(min, max), self.b.bounds(min, max));
+        // Blending adds at most a quarter of the smoothness to the maximum
+        let bulge = self.smoothness.max(0.0) * 0.25;
+        (a.0.max(b.0), a.1.max(b.1) + bulge)
+    }
+}
+
+/// Samples both densities and combines their values
+fn combine_batch(
+    a: &BoxedDensity,
+    b: &BoxedDensity,
+    points: &[Vec3],
+    out: &mut [f32],
+    combine: impl Fn(f32, f32) -> f32,
+) {
+    a.sample_batch(points, out);
+    let mut values = vec![0.0; points.len()];
+    b.sample_batch(points, &mut values);
+    for (out, value) in out.iter_mut().zip(values) {
+        *out = combine(*out, value);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn noise_stays_within_limits() {
+        let noise_types = [
+            NoiseType::Value,
+            NoiseType::ValueFractal,
+            NoiseType::Perlin,
+            NoiseType::PerlinFractal,
+            NoiseType::Simplex,
+            NoiseType::SimplexFractal,
+            NoiseType::Cellular,
+            NoiseType::WhiteNoise,
+            NoiseType::Cubic,
+            NoiseType::CubicFractal,
+        ];
+        let fractal_types = [
+            FractalType::FBM,
+            FractalType::Billow,
+            FractalType::RigidMulti,
+        ];
+        for noise_type in noise_types {
+            for fractal_type in fractal_types {
+                for frequency in [0.01, 0.05, 0.3, 1.0, 2.5] {
+                    let settings = NoiseSettings {
+                        noise_type,
+                        fractal_type,
+                        octaves: 3,
+                        frequency,
+                        ..default()
+                    };
+                    let Some((max_value, max_gradient)) = settings.limits() else { continue };
+                    let noise = NoiseLayer::new(7, settings, 1.0);
+                    let step = 0.01 / frequency;
+                    for i in 0..2000 {
+                        let i = i as f32;
+                        let point =
+                            Vec3::new(i * 0.713, (i * 0.37).sin() * 40.0, i * -0.291) / frequency;
+                        let value = noise.sample(point);
+                        assert!(value.abs() <= max_value, "{settings:?} at {point}: {value}");
+                        // Differences over a short step can't exceed the gradient
+                        for axis in [Vec3::X, Vec3::Y, Vec3::Z, Vec3::ONE.normalize()] {
+                            let change = (noise.sample(point + axis * step) - value).abs();
+                            assert!(
+                                change <= max_gradient * step * 1.001 + 1.0e-6,
+                                "{settings:?} at {point}: {} > {max_gradient}",
+                                change / step
+                            );
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
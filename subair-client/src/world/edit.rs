@@ -0,0 +1,265 @@
+use super::chunks::{ChunkSettings, LoadedChunks};
+use super::density::DensityFunction;
+use super::generate;
+use super::grid::DensityGrid;
+use super::marching_cubes_tables::POINT_OFFSETS;
+use super::physics::{self, ChunkColliders};
+use super::queue::GenerationQueue;
+use super::{WorldDensity, WorldInfo};
+use bevy::{
+    prelude::*,
+    utils::{HashMap, HashSet},
+};
+use std::sync::Arc;
+use tracing::debug;
+
+#[derive(Debug, Clone, Copy)]
+pub struct TerrainEdit {
+    pub center: Vec3,
+    pub shape: EditShape,
+    pub mode: EditMode,
+    /// Density change at the center of the shape
+    pub strength: f32,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum EditShape {
+    Sphere { radius: f32 },
+    Box { half_extents: Vec3 },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EditMode {
+    Add,
+    Subtract,
+}
+
+impl EditShape {
+    fn half_extents(&self) -> Vec3 {
+        match self {
+            EditShape::Sphere { radius } => Vec3::splat(*radius),
+            EditShape::Box { half_extents } => *half_extents,
+        }
+    }
+
+    // Influence of the edit at a point relative to its center
+    fn weight(&self, relative: Vec3) -> f32 {
+        match self {
+            EditShape::Sphere { radius } => (1.0 - relative.length() / radius).max(0.0),
+            EditShape::Box { half_extents } => {
+                if relative.abs().cmple(*half_extents).all() {
+                    1.0
+                } else {
+                    0.0
+                }
+            }
+        }
+    }
+}
+
+impl EditMode {
+    fn sign(&self) -> f32 {
+        match self {
+            // Density above the floor is solid
+            EditMode::Add => 1.0,
+            EditMode::Subtract => -1.0,
+        }
+    }
+}
+
+/// Densities of edited chunks, replacing the world density
+#[derive(Debug, Resource, Default)]
+pub struct TerrainEdits(HashMap<IVec3, Arc<DensityGrid>>);
+
+/// Edited density grids of a chunk and its neighbours, indexed by
+/// `neighbourhood_index`
+pub type Neighbourhood = [Option<Arc<DensityGrid>>; 27];
+
+fn neighbourhood_index(relative: IVec3) -> usize {
+    ((relative.x + 1) * 9 + (relative.y + 1) * 3 + relative.z + 1) as usize
+}
+
+/// World density with the edits around a chunk applied
+pub struct EditedDensity<'a> {
+    base: &'a dyn DensityFunction,
+    edits: &'a Neighbourhood,
+    chunk_offset: Vec3,
+    size: i32,
+}
+
+impl<'a> EditedDensity<'a> {
+    pub fn new(
+        base: &'a dyn DensityFunction,
+        edits: &'a Neighbourhood,
+        chunk_offset: Vec3,
+        size: usize,
+    ) -> Self {
+        Self {
+            base,
+            edits,
+            chunk_offset,
+            size: size as i32,
+        }
+    }
+
+    fn sample_lattice(&self, point: IVec3) -> f32 {
+        let neighbour = IVec3::new(
+            point.x.div_euclid(self.size),
+            point.y.div_euclid(self.size),
+            point.z.div_euclid(self.size),
+        );
+        if neighbour.abs().max_element() > 1 {
+            return self.base.sample(point.as_vec3() + self.chunk_offset);
+        }
+        match &self.edits[neighbourhood_index(neighbour)] {
+            Some(grid) => grid.get((point - neighbour * self.size).as_uvec3()),
+            None => self.base.sample(point.as_vec3() + self.chunk_offset),
+        }
+    }
+}
+
+impl DensityFunction for EditedDensity<'_> {
+    fn sample(&self, point: Vec3) -> f32 {
+        let local = point - self.chunk_offset;
+        let base = local.floor();
+        if base == local {
+            return self.sample_lattice(base.as_ivec3());
+        }
+        // Edits are only known on the lattice, interpolate between the samples
+        let t = local - base;
+        POINT_OFFSETS
+            .iter()
+            .map(|offset| {
+                let offset = generate::point_to_ivec3(*offset);
+                let o = offset.as_vec3();
+                let weights = o * t + (Vec3::ONE - o) * (Vec3::ONE - t);
+                weights.x * weights.y * weights.z * self.sample_lattice(base.as_ivec3() + offset)
+            })
+            .sum()
+    }
+}
+
+impl TerrainEdits {
+    pub fn clear(&mut self) {
+        self.0.clear();
+    }
+
+    pub fn insert(&mut self, coord: IVec3, grid: Arc<DensityGrid>) {
+        self.0.insert(coord, grid);
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (IVec3, &Arc<DensityGrid>)> {
+        self.0.iter().map(|(coord, grid)| (*coord, grid))
+    }
+
+    /// Drops grids that don't match the chunk size, returning how many were dropped
+    pub fn retain_size(&mut self, chunk_size: usize) -> usize {
+        let len = self.0.len();
+        self.0.retain(|_, grid| grid.size() == chunk_size);
+        len - self.0.len()
+    }
+
+    pub fn neighbourhood(&self, coord: IVec3) -> Neighbourhood {
+        let mut neighbourhood = Neighbourhood::default();
+        if self.0.is_empty() {
+            return neighbourhood;
+        }
+        for x in -1..=1 {
+            for y in -1..=1 {
+                for z in -1..=1 {
+                    let relative = IVec3::new(x, y, z);
+                    neighbourhood[neighbourhood_index(relative)] =
+                        self.0.get(&(coord + relative)).cloned();
+                }
+            }
+        }
+        neighbourhood
+    }
+}
+
+// Chunks whose samples lie between `min` and `max`
+fn chunk_range(min: Vec3, max: Vec3, chunk_size: usize) -> impl Iterator<Item = IVec3> {
+    // Chunks share their face samples, so an edit on a face changes both chunks
+    let min_chunk = (min / chunk_size as f32).ceil().as_ivec3() - IVec3::ONE;
+    let max_chunk = (max / chunk_size as f32).floor().as_ivec3();
+    (min_chunk.x..=max_chunk.x).flat_map(move |x| {
+        (min_chunk.y..=max_chunk.y)
+            .flat_map(move |y| (min_chunk.z..=max_chunk.z).map(move |z| IVec3::new(x, y, z)))
+    })
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn apply_terrain_edits(
+    mut commands: Commands,
+    mut events: EventReader<TerrainEdit>,
+    info: Res<WorldInfo>,
+    density: Res<WorldDensity>,
+    mut edits: ResMut<TerrainEdits>,
+    loaded: Res<LoadedChunks>,
+    settings: Res<ChunkSettings>,
+    colliders: Res<ChunkColliders>,
+    mut queue: ResMut<GenerationQueue>,
+) {
+    let mut dirty = HashSet::new();
+    for edit in events.iter() {
+        let min = edit.center - edit.shape.half_extents();
+        let max = edit.center + edit.shape.half_extents();
+        for coord in chunk_range(min, max, info.chunk_size) {
+            let offset = info.chunk_offset(coord);
+            let grid = edits.0.entry(coord).or_insert_with(|| {
+                Arc::new(DensityGrid::from_density(
+                    density.0.as_ref(),
+                    offset,
+                    info.chunk_size,
+                ))
+            });
+            apply_edit(Arc::make_mut(grid), offset, edit);
+        }
+        // Neighbours see the edit through the apron used for their normals
+        dirty.extend(chunk_range(
+            min - Vec3::ONE,
+            max + Vec3::ONE,
+            info.chunk_size,
+        ));
+    }
+
+    for coord in dirty.iter().copied() {
+        // Unloaded chunks pick up the edits once they are loaded
+        let Some((entity, lod)) = loaded.get(coord) else { continue };
+        // Colliders are rebuilt right away, so bodies don't get stuck in drilled tunnels
+        if colliders.contains(coord) {
+            let neighbourhood = edits.neighbourhood(coord);
+            if let Some(task) =
+                physics::collider_task(&info, &density, &settings, coord, neighbourhood)
+            {
+                commands.entity(entity).insert(task);
+            }
+        }
+        queue.request(&mut commands, coord, entity, lod);
+    }
+    if !dirty.is_empty() {
+        debug!(num_chunks = dirty.len(), "Applied terrain edits");
+    }
+}
+
+fn apply_edit(grid: &mut DensityGrid, offset: Vec3, edit: &TerrainEdit) {
+    let half_extents = edit.shape.half_extents();
+    let size = grid.size() as f32;
+    let min = (edit.center - half_extents - offset)
+        .ceil()
+        .clamp(Vec3::ZERO, Vec3::splat(size));
+    let max = (edit.center + half_extents - offset)
+        .floor()
+        .clamp(Vec3::ZERO, Vec3::splat(size));
+    let (min, max) = (min.as_uvec3(), max.as_uvec3());
+    for x in min.x..=max.x {
+        for y in min.y..=max.y {
+            for z in min.z..=max.z {
+                let point = UVec3::new(x, y, z);
+                let position = offset + point.as_vec3();
+                let weight = edit.shape.weight(position - edit.center);
+                *grid.get_mut(point) += edit.mode.sign() * edit.strength * weight;
+            }
+        }
+    }
+}
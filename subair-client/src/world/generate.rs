@@ -1,52 +1,337 @@
-use super::kd_tree::{construct_tree, points_in_range};
+use super::density::DensityFunction;
+use super::edit::{EditedDensity, Neighbourhood};
+use super::grid::SampleGrid;
 use super::marching_cubes_tables::{EDGES, POINT_OFFSETS, TRIANGLE_LISTS};
+use super::material::{layer_attributes, ATTRIBUTE_MATERIAL_ID};
 use super::normals::calculate_normals;
+use crate::kd_tree::KdTree;
 use bevy::render::mesh::Indices;
 use bevy::{prelude::*, render::render_resource::PrimitiveTopology};
 use bevy_rapier3d::prelude::*;
-use bracket_noise::prelude::*;
-use std::time::Instant;
+use serde::Deserialize;
+use std::collections::HashSet;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 use tracing::{debug, instrument};
 
 const FLOOR: f32 = 0.0;
 const VERTEX_GROUP_MAX_DISTANCE: f32 = 1.0e-7;
+const GRADIENT_STEP: f32 = 0.1;
+// How often chunks are split into octants to find out whether they are empty or solid
+const CLASSIFY_DEPTH: u32 = 2;
 
-#[instrument(skip(offset))]
-pub fn generate_world(seed: u64, offset: Vec3, size: usize) -> (Mesh, Collider, Vec3) {
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Reflect, Deserialize)]
+pub enum NormalMode {
+    /// Averaged face normals, uses the apron around the chunk
+    #[default]
+    Faces,
+    /// Central differences of the density at every vertex
+    Gradient,
+}
+
+/// How the vertices marching cubes emits for every triangle are merged
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Reflect, Deserialize)]
+pub enum Welding {
+    /// Vertices are shared by cube edge while meshing
+    #[default]
+    EdgeIndex,
+    /// Vertices are merged afterwards by searching a k-d tree for equal ones
+    KdTree,
+}
+
+/// What a chunk contains, judged by the bounds of its density
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ChunkContent {
+    /// Only water, there is nothing to mesh
+    Empty,
+    /// Only terrain, the surface is somewhere else
+    Solid,
+    /// The surface may cross the chunk
+    Mixed,
+}
+
+pub struct GeneratedChunk {
+    pub mesh: Mesh,
+    pub offset: Vec3,
+    pub stats: GenerationStats,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct GenerationStats {
+    /// Vertices emitted by marching cubes for the chunk, three per triangle
+    pub raw_vertices: usize,
+    /// Vertices of the chunk after deduplication, without skirts
+    pub vertices: usize,
+    pub skirt_vertices: usize,
+    pub triangles: usize,
+    pub duration: Duration,
+}
+
+impl GenerationStats {
+    /// Fraction of the raw vertices removed by deduplication
+    pub fn dedup_ratio(&self) -> f32 {
+        if self.raw_vertices == 0 {
+            return 0.0;
+        }
+        1.0 - self.vertices as f32 / self.raw_vertices as f32
+    }
+}
+
+/// Generates the mesh of a chunk spanning `size` units on every axis,
+/// sampling every `1 << lod` units. Edited densities are read from `edits`
+#[allow(clippy::too_many_arguments)]
+#[instrument(skip(density, offset, edits))]
+pub fn generate_world(
+    density: Arc<dyn DensityFunction>,
+    seed: u64,
+    normal_mode: NormalMode,
+    welding: Welding,
+    offset: Vec3,
+    size: usize,
+    lod: u32,
+    edits: Neighbourhood,
+) -> GeneratedChunk {
     let start = Instant::now();
-    let simple_vertices = marching_cubes(size, size, size, seed, offset);
+    let stride = 1 << lod;
+    debug_assert_eq!(
+        size % stride,
+        0,
+        "Chunk size must be a multiple of the stride"
+    );
+    let samples = size / stride + 1;
+    let edited = EditedDensity::new(density.as_ref(), &edits, offset, size);
+    let density = if edits.iter().any(Option::is_some) {
+        &edited as &dyn DensityFunction
+    } else {
+        density.as_ref()
+    };
+
+    let apron = normal_mode == NormalMode::Faces;
+    let grid = SampleGrid::new(density, offset, samples, stride, apron);
+    debug!(
+        "Sampled density in {:.3}ms",
+        start.elapsed().as_secs_f32() * 1000.0
+    );
+    let (vertices, mut indices, num_chunk_indices) = match welding {
+        Welding::EdgeIndex => marching_cubes_indexed(&grid, stride),
+        Welding::KdTree => {
+            let (simple_vertices, apron_vertices) = marching_cubes(&grid, stride);
+            let num_chunk_indices = simple_vertices.len();
+            let (vertices, indices) =
+                deduplicate_vertices(simple_vertices.into_iter().chain(apron_vertices).collect());
+            (vertices, indices, num_chunk_indices)
+        }
+    };
     debug!(
-        num_vertices = simple_vertices.len(),
+        num_vertices = vertices.len(),
+        num_indices = indices.len(),
         "Generated mesh in {:.3}ms",
         start.elapsed().as_secs_f32() * 1000.0
     );
-    let (vertices, indices) = deduplicate_vertices(simple_vertices);
-    let vertices: Vec<_> = vertices.into_iter().map(|v| v.to_array()).collect();
-    let normals = calculate_normals(&vertices, &indices);
+    let mut vertices: Vec<_> = vertices.into_iter().map(|v| v.to_array()).collect();
+    let mut normals = match normal_mode {
+        NormalMode::Faces => calculate_normals(&vertices, &indices),
+        NormalMode::Gradient => gradient_normals(&vertices, offset, density),
+    };
+
+    // The apron triangles were only needed for the normals along the chunk faces.
+    // Both weldings number the vertices in order of first use, so the vertices
+    // used by the chunk itself come before the ones only used by the apron
+    indices.truncate(num_chunk_indices);
+    let num_chunk_vertices = indices.iter().max().map_or(0, |i| *i as usize + 1);
+    vertices.truncate(num_chunk_vertices);
+    normals.truncate(num_chunk_vertices);
 
+    // Skirts only hide cracks in the rendered mesh, the collider doesn't need them
+    let (mesh_vertices, mesh_normals, mesh_indices) =
+        add_skirts(&vertices, &normals, &indices, size as f32, stride as f32);
+    let (colors, material_ids) = layer_attributes(&mesh_vertices, &mesh_normals, offset, seed);
+    let mut stats = GenerationStats {
+        raw_vertices: num_chunk_indices,
+        vertices: vertices.len(),
+        skirt_vertices: mesh_vertices.len() - vertices.len(),
+        triangles: indices.len() / 3,
+        duration: Duration::ZERO,
+    };
     let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
-    mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, vertices.clone());
-    mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, normals);
-    mesh.set_indices(Some(Indices::U32(indices.clone())));
+    mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, mesh_vertices);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, mesh_normals);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_COLOR, colors);
+    mesh.insert_attribute(ATTRIBUTE_MATERIAL_ID, material_ids);
+    mesh.set_indices(Some(Indices::U32(mesh_indices)));
 
-    let collider_indices = {
-        let mut vec = vec![];
-        for i in 0..(indices.len() / 3) {
-            vec.push([indices[i * 3], indices[i * 3 + 1], indices[i * 3 + 2]]);
-        }
-        vec
+    stats.duration = start.elapsed();
+    GeneratedChunk {
+        mesh,
+        offset,
+        stats,
+    }
+}
+
+/// Generates the collider of a chunk, sampling every `1 << lod` units so it
+/// can be coarser than the mesh. `None` if the chunk has no surface
+#[instrument(skip(density, offset, edits))]
+pub fn generate_collider(
+    density: Arc<dyn DensityFunction>,
+    offset: Vec3,
+    size: usize,
+    lod: u32,
+    edits: Neighbourhood,
+) -> Option<Collider> {
+    let start = Instant::now();
+    let (vertices, indices) = collider_mesh(density.as_ref(), offset, size, lod, &edits);
+    let collider = build_collider(&vertices, &indices);
+    debug!(
+        num_triangles = indices.len() / 3,
+        "Generated collider in {:.3}ms",
+        start.elapsed().as_secs_f32() * 1000.0
+    );
+    collider
+}
+
+/// Surface of the chunk without an apron or skirts
+pub fn collider_mesh(
+    density: &dyn DensityFunction,
+    offset: Vec3,
+    size: usize,
+    lod: u32,
+    edits: &Neighbourhood,
+) -> (Vec<[f32; 3]>, Vec<u32>) {
+    let stride = 1 << lod;
+    let edited = EditedDensity::new(density, edits, offset, size);
+    let density = if edits.iter().any(Option::is_some) {
+        &edited as &dyn DensityFunction
+    } else {
+        density
     };
-    let collider = Collider::trimesh(
-        vertices.into_iter().map(|v| v.into()).collect(),
-        collider_indices,
+    let grid = SampleGrid::new(density, offset, size / stride + 1, stride, false);
+    let (vertices, indices, _) = marching_cubes_indexed(&grid, stride);
+    (
+        vertices.into_iter().map(|v| v.to_array()).collect(),
+        indices,
+    )
+}
+
+/// Trimesh collider of the chunk, `None` if it has no triangles
+pub fn build_collider(vertices: &[[f32; 3]], indices: &[u32]) -> Option<Collider> {
+    let collider_indices: Vec<_> = indices
+        .chunks_exact(3)
+        .map(|triangle| [triangle[0], triangle[1], triangle[2]])
+        .collect();
+    // Trimesh colliders need at least one triangle
+    (!collider_indices.is_empty()).then(|| {
+        Collider::trimesh(
+            vertices.iter().map(|v| Vec3::from(*v)).collect(),
+            collider_indices,
+        )
+    })
+}
+
+/// Classifies the samples of the chunk at `offset`, without its apron
+pub fn classify_chunk(density: &dyn DensityFunction, offset: Vec3, size: usize) -> ChunkContent {
+    let max = offset + Vec3::splat(size as f32);
+    classify(density, offset, max, CLASSIFY_DEPTH)
+}
+
+/// Classifies the box from `min` to `max` by the bounds of the density,
+/// splitting it into octants up to `depth` times when they are inconclusive
+pub fn classify(density: &dyn DensityFunction, min: Vec3, max: Vec3, depth: u32) -> ChunkContent {
+    let (low, high) = density.bounds(min, max);
+    if low > FLOOR {
+        return ChunkContent::Solid;
+    }
+    if high <= FLOOR {
+        return ChunkContent::Empty;
+    }
+    if depth == 0 {
+        return ChunkContent::Mixed;
+    }
+    let center = (min + max) * 0.5;
+    let mut content = None;
+    for offset in POINT_OFFSETS {
+        let offset = point_to_ivec3(offset).as_vec3();
+        let octant_min = min + (center - min) * offset;
+        let octant_max = center + (max - center) * offset;
+        let octant = classify(density, octant_min, octant_max, depth - 1);
+        // Empty next to solid octants means the surface is between them
+        if octant == ChunkContent::Mixed || content.is_some_and(|content| content != octant) {
+            return ChunkContent::Mixed;
+        }
+        content = Some(octant);
+    }
+    content.unwrap()
+}
+
+/// Extrudes every open edge lying on a chunk face into the solid, so that
+/// neighbouring chunks of a different LOD don't show cracks between them
+#[instrument(skip(vertices, normals, indices))]
+fn add_skirts(
+    vertices: &[[f32; 3]],
+    normals: &[[f32; 3]],
+    indices: &[u32],
+    size: f32,
+    depth: f32,
+) -> (Vec<[f32; 3]>, Vec<[f32; 3]>, Vec<u32>) {
+    let start = Instant::now();
+    // Edges shared by two triangles are stored once in each direction
+    let mut open_edges = HashSet::new();
+    for triangle in indices.chunks_exact(3) {
+        for i in 0..3 {
+            let edge = (triangle[i], triangle[(i + 1) % 3]);
+            if !open_edges.remove(&(edge.1, edge.0)) {
+                open_edges.insert(edge);
+            }
+        }
+    }
+    // Keep the output independent of the hash order
+    let mut open_edges: Vec<_> = open_edges.into_iter().collect();
+    open_edges.sort_unstable();
+
+    let mut out_vertices = vertices.to_vec();
+    let mut out_normals = normals.to_vec();
+    let mut out_indices = indices.to_vec();
+    for (a, b) in open_edges {
+        let pa = Vec3::from(vertices[a as usize]);
+        let pb = Vec3::from(vertices[b as usize]);
+        let Some(axis) = boundary_axis(pa, pb, size) else { continue };
+
+        let base = out_vertices.len() as u32;
+        for (index, point) in [(a, pa), (b, pb)] {
+            let normal = Vec3::from(normals[index as usize]);
+            // Keep the skirt inside the face plane
+            let mut direction = -normal;
+            direction[axis] = 0.0;
+            let skirt_point = point + direction.normalize_or_zero() * depth;
+            out_vertices.push(skirt_point.into());
+            out_normals.push(normals[index as usize]);
+        }
+        // The neighbouring triangle walks the edge from a to b, so the skirt
+        // walks it from b to a to face the same way
+        out_indices.extend_from_slice(&[b, a, base, b, base, base + 1]);
+    }
+    debug!(
+        num_vertices = out_vertices.len() - vertices.len(),
+        "Added skirts in {:.3}ms",
+        start.elapsed().as_secs_f32() * 1000.0
     );
-    (mesh, collider, offset)
+    (out_vertices, out_normals, out_indices)
+}
+
+fn boundary_axis(p1: Vec3, p2: Vec3, size: f32) -> Option<usize> {
+    const EPSILON: f32 = 1.0e-4;
+    (0..3).find(|&axis| {
+        [0.0, size]
+            .iter()
+            .any(|face| (p1[axis] - face).abs() < EPSILON && (p2[axis] - face).abs() < EPSILON)
+    })
 }
 
+/// Merges equal vertices, returning the remaining ones and the index of every
+/// input vertex among them
 #[instrument(skip(input))]
-fn deduplicate_vertices(input: Vec<Vec3>) -> (Vec<Vec3>, Vec<u32>) {
+pub fn deduplicate_vertices(input: Vec<Vec3>) -> (Vec<Vec3>, Vec<u32>) {
     let start = Instant::now();
-    let tree = construct_tree(&input);
+    let tree = KdTree::new(input.iter().copied().enumerate().map(|(i, p)| (p, i)));
     debug!(
         "Constructed tree in {:.3}ms",
         start.elapsed().as_secs_f32() * 1000.0
@@ -62,11 +347,10 @@ fn deduplicate_vertices(input: Vec<Vec3>) -> (Vec<Vec3>, Vec<u32>) {
         }
         let vert_index = vertices.len();
         vertices.push(*point);
-        points_in_range(
-            &tree,
+        tree.within_radius(
             *point,
             VERTEX_GROUP_MAX_DISTANCE,
-            |(_, close_point_index)| {
+            |_, &close_point_index| {
                 indices[close_point_index] = Some(vert_index);
             },
         );
@@ -83,69 +367,412 @@ fn deduplicate_vertices(input: Vec<Vec3>) -> (Vec<Vec3>, Vec<u32>) {
     (vertices, indices)
 }
 
-#[instrument(skip_all)]
-fn marching_cubes(
-    width: usize,
-    height: usize,
-    depth: usize,
-    seed: u64,
-    noise_offset: Vec3,
-) -> Vec<Vec3> {
-    let noise = init_noise(seed);
+/// Normals pointing out of the solid, from central differences of the density
+fn gradient_normals(
+    vertices: &[[f32; 3]],
+    offset: Vec3,
+    density: &dyn DensityFunction,
+) -> Vec<[f32; 3]> {
+    let start = Instant::now();
+    const AXES: [Vec3; 3] = [Vec3::X, Vec3::Y, Vec3::Z];
+    // Both sides along every axis for every vertex, sampled at once
+    let points: Vec<_> = vertices
+        .iter()
+        .flat_map(|vertex| {
+            let point = Vec3::from(*vertex) + offset;
+            AXES.into_iter()
+                .flat_map(move |axis| [point + axis * GRADIENT_STEP, point - axis * GRADIENT_STEP])
+        })
+        .collect();
+    let mut values = vec![0.0; points.len()];
+    density.sample_batch(&points, &mut values);
+    let normals = values
+        .chunks_exact(6)
+        .map(|values| {
+            let gradient = Vec3::new(
+                values[0] - values[1],
+                values[2] - values[3],
+                values[4] - values[5],
+            );
+            // The density increases towards the solid
+            (-gradient).normalize_or_zero().into()
+        })
+        .collect();
+    debug!(
+        "Generated gradient normals in {:.3}ms",
+        start.elapsed().as_secs_f32() * 1000.0
+    );
+    normals
+}
+
+/// Returns the vertices of the cells between the samples of the chunk and of
+/// the cells in the apron of the grid, if it has one
+#[instrument(skip(grid))]
+pub fn marching_cubes(grid: &SampleGrid, stride: usize) -> (Vec<Vec3>, Vec<Vec3>) {
     let mut vertices = vec![];
+    let mut apron_vertices = vec![];
+    march_cells(grid, stride, |in_chunk, _, point| {
+        if in_chunk {
+            vertices.push(point);
+        } else {
+            apron_vertices.push(point);
+        }
+    });
+    (vertices, apron_vertices)
+}
 
-    for x in 0..(width - 1) {
-        for y in 0..(height - 1) {
-            for z in 0..(depth - 1) {
-                let mut configuration = 0u8;
-                let mut values = [0.0; 8];
-                for (i, offset) in POINT_OFFSETS.iter().enumerate() {
-                    let p = add_points([x, y, z], *offset);
-                    let p = point_to_vec3(p);
-                    let value = sample_noise(p + noise_offset, &noise);
-                    if value > FLOOR {
-                        configuration |= 1 << i;
-                    }
-                    values[i] = value;
-                }
+/// Marching cubes sharing the vertex of every cube edge between the cells
+/// using it, so the vertices don't need to be deduplicated afterwards.
+/// Returns the vertices, the indices and how many of the indices belong to
+/// the chunk, the rest belong to the apron
+#[instrument(skip(grid))]
+pub fn marching_cubes_indexed(grid: &SampleGrid, stride: usize) -> (Vec<Vec3>, Vec<u32>, usize) {
+    let first = grid.first();
+    let lattice = grid.samples() as usize;
+    // Index of the vertex on every edge, by lowest sample and axis of the edge,
+    // followed by the one on the sample itself
+    let mut edge_vertices = vec![u32::MAX; lattice * lattice * lattice * 4];
+    let mut vertices = vec![];
+    let mut indices = vec![];
+    let mut num_chunk_indices = 0;
+    march_cells(grid, stride, |in_chunk, (sample, axis), point| {
+        let sample = (sample - IVec3::splat(first)).as_uvec3();
+        let sample =
+            (sample.x as usize * lattice + sample.y as usize) * lattice + sample.z as usize;
+        let edge = sample * 4 + axis;
+        if edge_vertices[edge] == u32::MAX {
+            edge_vertices[edge] = vertices.len() as u32;
+            vertices.push(point);
+        }
+        indices.push(edge_vertices[edge]);
+        num_chunk_indices += in_chunk as usize;
+    });
+    (vertices, indices, num_chunk_indices)
+}
+
+/// Calls `emit` for every triangle corner, with whether its cell is part of the
+/// chunk, the cube edge it lies on as its lowest sample and axis, and its
+/// position. Corners exactly on a sample are shared by all of its edges and
+/// use axis 3 instead. Cells of the chunk come first, then those of the apron
+fn march_cells(grid: &SampleGrid, stride: usize, mut emit: impl FnMut(bool, (IVec3, usize), Vec3)) {
+    let cells = IVec3::splat(grid.chunk_samples() - 1);
+    let mut march_cell = |cell: IVec3, in_chunk: bool| {
+        let mut configuration = 0u8;
+        let mut values = [0.0; 8];
+        for (i, point_offset) in POINT_OFFSETS.iter().enumerate() {
+            let value = grid.get(cell + point_to_ivec3(*point_offset));
+            if value > FLOOR {
+                configuration |= 1 << i;
+            }
+            values[i] = value;
+        }
 
-                let triangles = TRIANGLE_LISTS[configuration as usize];
-                for edge in triangles.iter().flatten().copied() {
-                    let [vertex1, vertex2] = EDGES[edge];
-                    let point1 = point_to_vec3(add_points([x, y, z], POINT_OFFSETS[vertex1]));
-                    let point2 = point_to_vec3(add_points([x, y, z], POINT_OFFSETS[vertex2]));
-                    let value1 = values[vertex1];
-                    let value2 = values[vertex2];
-                    let difference = value2 - value1;
-                    let distance_to_ground = FLOOR - value1;
-                    let floor_point = point1.lerp(point2, distance_to_ground / difference);
-                    vertices.push(floor_point);
+        let triangles = TRIANGLE_LISTS[configuration as usize];
+        for edge in triangles.iter().flatten().copied() {
+            let [vertex1, vertex2] = EDGES[edge];
+            let sample1 = cell + point_to_ivec3(POINT_OFFSETS[vertex1]);
+            let sample2 = cell + point_to_ivec3(POINT_OFFSETS[vertex2]);
+            let floor_point = surface_point(
+                sample1.as_vec3(),
+                sample2.as_vec3(),
+                values[vertex1],
+                values[vertex2],
+            );
+            let key = if floor_point == sample1.as_vec3() {
+                (sample1, 3)
+            } else if floor_point == sample2.as_vec3() {
+                (sample2, 3)
+            } else {
+                let axis = (0..3).find(|&axis| sample1[axis] != sample2[axis]).unwrap();
+                (sample1.min(sample2), axis)
+            };
+            emit(in_chunk, key, floor_point * stride as f32);
+        }
+    };
+
+    for x in 0..cells.x {
+        for y in 0..cells.y {
+            for z in 0..cells.z {
+                march_cell(IVec3::new(x, y, z), true);
+            }
+        }
+    }
+    if grid.first() == 0 {
+        return;
+    }
+    for x in -1..=cells.x {
+        for y in -1..=cells.y {
+            for z in -1..=cells.z {
+                let cell = IVec3::new(x, y, z);
+                if cell.cmpge(IVec3::ZERO).all() && cell.cmplt(cells).all() {
+                    continue;
                 }
+                march_cell(cell, false);
             }
         }
     }
-    vertices
 }
 
-fn point_to_vec3(point: [usize; 3]) -> Vec3 {
-    Vec3::new(point[0] as f32, point[1] as f32, point[2] as f32)
+/// Point on the edge between two samples where the density crosses the floor.
+/// Every cell sharing the edge gets exactly the same point, so deduplication
+/// can't leave cracks, and the point stays on the edge even when the values are
+/// equal, infinite or too close to divide by
+fn surface_point(point1: Vec3, point2: Vec3, value1: f32, value2: f32) -> Vec3 {
+    let ((from, from_value), (to, to_value)) = if point1.to_array() < point2.to_array() {
+        ((point1, value1), (point2, value2))
+    } else {
+        ((point2, value2), (point1, value1))
+    };
+    let t = (FLOOR - from_value) / (to_value - from_value);
+    let t = if t.is_finite() {
+        t.clamp(0.0, 1.0)
+    } else {
+        0.5
+    };
+    from.lerp(to, t)
 }
 
-fn sample_noise(point: Vec3, noise: &FastNoise) -> f32 {
-    noise.get_noise3d(point.x, point.y, point.z)
+pub fn point_to_ivec3(point: [usize; 3]) -> IVec3 {
+    IVec3::new(point[0] as i32, point[1] as i32, point[2] as i32)
 }
 
-fn add_points(p1: [usize; 3], p2: [usize; 3]) -> [usize; 3] {
-    [p1[0] + p2[0], p1[1] + p2[1], p1[2] + p2[2]]
-}
+#[cfg(test)]
+mod tests {
+    use super::super::density::{
+        Add, Cuboid, DomainWarp, HeightBias, Negate, NoiseLayer, NoiseSettings, SmoothUnion, Sphere,
+    };
+    use super::*;
+    use bevy::render::mesh::VertexAttributeValues;
+    use std::collections::HashMap;
+
+    const CENTER: Vec3 = Vec3::splat(16.0);
+
+    #[test]
+    fn normal_modes_match_sphere() {
+        // Sphere in the middle of a chunk
+        let sphere = Sphere {
+            center: CENTER,
+            radius: 10.0,
+        };
+        let (vertices, apron_vertices) =
+            marching_cubes(&SampleGrid::new(&sphere, Vec3::ZERO, 33, 1, true), 1);
+        assert!(apron_vertices.is_empty());
+        let (vertices, indices) = deduplicate_vertices(vertices);
+        let vertices: Vec<_> = vertices.into_iter().map(|v| v.to_array()).collect();
+
+        let face_normals = calculate_normals(&vertices, &indices);
+        let gradient_normals = gradient_normals(&vertices, Vec3::ZERO, &sphere);
+        for ((vertex, face), gradient) in vertices.iter().zip(face_normals).zip(gradient_normals) {
+            let expected = (Vec3::from(*vertex) - CENTER).normalize();
+            let face = Vec3::from(face);
+            let gradient = Vec3::from(gradient);
+            assert!(gradient.dot(expected) > 0.999, "{gradient} != {expected}");
+            assert!(face.dot(expected) > 0.95, "{face} != {expected}");
+            assert!(face.dot(gradient) > 0.95, "{face} != {gradient}");
+        }
+    }
+
+    fn mesh_chunk(density: &dyn DensityFunction, size: usize) -> (Vec<Vec3>, Vec<u32>) {
+        let (vertices, _) =
+            marching_cubes(&SampleGrid::new(density, Vec3::ZERO, size + 1, 1, false), 1);
+        deduplicate_vertices(vertices)
+    }
 
-fn init_noise(seed: u64) -> FastNoise {
-    let mut noise = FastNoise::seeded(seed);
-    noise.set_noise_type(NoiseType::PerlinFractal);
-    noise.set_fractal_type(FractalType::FBM);
-    noise.set_fractal_octaves(1);
-    noise.set_fractal_gain(0.6);
-    noise.set_fractal_lacunarity(2.0);
-    noise.set_frequency(0.05);
-    noise
+    /// Counts how often every directed edge is used. Triangles collapsed by
+    /// deduplication have no area and are left out
+    fn directed_edges(indices: &[u32]) -> HashMap<(u32, u32), usize> {
+        let mut edges = HashMap::new();
+        for triangle in indices.chunks_exact(3) {
+            if triangle[0] == triangle[1]
+                || triangle[1] == triangle[2]
+                || triangle[2] == triangle[0]
+            {
+                continue;
+            }
+            for i in 0..3 {
+                *edges
+                    .entry((triangle[i], triangle[(i + 1) % 3]))
+                    .or_default() += 1;
+            }
+        }
+        edges
+    }
+
+    #[test]
+    fn sphere_is_watertight() {
+        // Off the sample lattice, so no vertex lands exactly on a sample
+        let sphere = Sphere {
+            center: Vec3::new(16.3, 15.8, 16.1),
+            radius: 9.6,
+        };
+        let (vertices, indices) = mesh_chunk(&sphere, 32);
+        let edges = directed_edges(&indices);
+        assert_eq!(edges.len(), indices.len(), "Degenerate triangles");
+        for (&(a, b), &count) in &edges {
+            assert_eq!(count, 1, "Edge {a}-{b} is used by several triangles");
+            assert!(edges.contains_key(&(b, a)), "Edge {a}-{b} is open");
+        }
+        // A closed surface without holes
+        let euler = vertices.len() as i64 - edges.len() as i64 / 2 + indices.len() as i64 / 3;
+        assert_eq!(euler, 2);
+    }
+
+    #[test]
+    fn noise_chunks_are_manifold() {
+        for seed in [1, 2, 3, 42] {
+            let noise = NoiseLayer::new(seed, NoiseSettings::default(), 1.0);
+            let (vertices, indices) = mesh_chunk(&noise, 32);
+            let edges = directed_edges(&indices);
+            for (&(a, b), &count) in &edges {
+                assert_eq!(count, 1, "Edge {a}-{b} is used by several triangles");
+                if !edges.contains_key(&(b, a)) {
+                    let (pa, pb) = (vertices[a as usize], vertices[b as usize]);
+                    assert!(
+                        boundary_axis(pa, pb, 32.0).is_some(),
+                        "Hole at {pa}-{pb} with seed {seed}"
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn flat_density_has_no_nan() {
+        struct Flat(f32);
+        impl DensityFunction for Flat {
+            fn sample(&self, point: Vec3) -> f32 {
+                // Crosses the floor between x = 4 and 5 with almost no difference
+                if point.x < 4.5 {
+                    self.0
+                } else {
+                    -self.0
+                }
+            }
+        }
+        for value in [f32::MIN_POSITIVE, 1.0e-45, f32::INFINITY] {
+            let (vertices, _) =
+                marching_cubes(&SampleGrid::new(&Flat(value), Vec3::ZERO, 9, 1, true), 1);
+            assert!(!vertices.is_empty());
+            for vertex in vertices {
+                assert!(vertex.is_finite(), "{vertex} with {value}");
+                assert!((4.0..=5.0).contains(&vertex.x), "{vertex} with {value}");
+            }
+        }
+    }
+
+    #[test]
+    fn weldings_match() {
+        // Without an offset some samples are on the lattice of the noise, where
+        // it is exactly zero, so vertices of several edges end in the same sample
+        for (seed, offset) in [
+            (1, Vec3::ZERO),
+            (2, Vec3::new(0.3, 0.6, 0.9)),
+            (3, Vec3::ZERO),
+        ] {
+            let noise = NoiseLayer::new(seed, NoiseSettings::default(), 1.0);
+            let grid = SampleGrid::new(&noise, offset, 17, 2, true);
+            let (vertices, apron_vertices) = marching_cubes(&grid, 2);
+            let num_chunk_indices = vertices.len();
+            let kd_tree =
+                deduplicate_vertices(vertices.into_iter().chain(apron_vertices).collect());
+            let (vertices, indices, num_indices) = marching_cubes_indexed(&grid, 2);
+            assert_eq!(num_indices, num_chunk_indices);
+            assert_eq!((vertices, indices), kd_tree);
+        }
+    }
+
+    #[test]
+    fn classification_matches_samples() {
+        let settings = NoiseSettings {
+            octaves: 3,
+            ..default()
+        };
+        let terrain = Add(vec![
+            Box::new(HeightBias {
+                height: 0.0,
+                gradient: 0.05,
+            }),
+            Box::new(DomainWarp::new(
+                1,
+                settings,
+                4.0,
+                Box::new(NoiseLayer::new(2, settings, 1.0)),
+            )),
+        ]);
+        let carved = SmoothUnion {
+            a: Box::new(Negate(Box::new(Cuboid {
+                center: Vec3::new(0.0, 40.0, 0.0),
+                half_extents: Vec3::splat(24.0),
+            }))),
+            b: Box::new(Sphere {
+                center: Vec3::new(8.0, 40.0, 8.0),
+                radius: 6.0,
+            }),
+            smoothness: 2.0,
+        };
+        for density in [&terrain as &dyn DensityFunction, &carved] {
+            let mut counts = HashMap::new();
+            for x in -2..2 {
+                for y in -6..6 {
+                    for z in -2..2 {
+                        let offset = IVec3::new(x, y, z).as_vec3() * 16.0;
+                        let content = classify_chunk(density, offset, 16);
+                        *counts.entry(content).or_insert(0) += 1;
+                        let grid = SampleGrid::new(density, offset, 17, 1, false);
+                        let solid = (0..17 * 17 * 17)
+                            .filter(|i| grid.get(IVec3::new(i / 289, i / 17 % 17, i % 17)) > FLOOR)
+                            .count();
+                        match content {
+                            ChunkContent::Empty => assert_eq!(solid, 0, "{offset}"),
+                            ChunkContent::Solid => assert_eq!(solid, 17 * 17 * 17, "{offset}"),
+                            ChunkContent::Mixed => {}
+                        }
+                    }
+                }
+            }
+            // Bounds that are too loose would never skip anything
+            assert!(counts.get(&ChunkContent::Empty) > Some(&0), "{counts:?}");
+            assert!(counts.get(&ChunkContent::Solid) > Some(&0), "{counts:?}");
+        }
+    }
+
+    #[test]
+    fn collider_matches_mesh() {
+        let density: Arc<dyn DensityFunction> = Arc::new(NoiseLayer::new(
+            1,
+            NoiseSettings {
+                octaves: 3,
+                ..default()
+            },
+            1.0,
+        ));
+        let chunk = generate_world(
+            density.clone(),
+            1,
+            NormalMode::Faces,
+            Welding::EdgeIndex,
+            Vec3::ZERO,
+            16,
+            0,
+            default(),
+        );
+        let (vertices, indices) = collider_mesh(density.as_ref(), Vec3::ZERO, 16, 0, &default());
+        // The mesh is the collider's surface followed by skirts
+        let Some(VertexAttributeValues::Float32x3(positions)) =
+            chunk.mesh.attribute(Mesh::ATTRIBUTE_POSITION)
+        else {
+            unreachable!()
+        };
+        let Some(Indices::U32(mesh_indices)) = chunk.mesh.indices() else {
+            unreachable!()
+        };
+        assert!(!indices.is_empty());
+        assert_eq!(vertices[..], positions[..vertices.len()]);
+        assert_eq!(indices[..], mesh_indices[..indices.len()]);
+        assert_eq!(indices.len() / 3, chunk.stats.triangles);
+
+        // Coarser colliders have fewer triangles
+        let (_, decimated) = collider_mesh(density.as_ref(), Vec3::ZERO, 16, 1, &default());
+        assert!(!decimated.is_empty() && decimated.len() < indices.len());
+    }
 }
@@ -0,0 +1,150 @@
+use super::density::DensityFunction;
+use bevy::prelude::*;
+
+/// Density samples of a chunk, one per unit on every axis including both faces
+#[derive(Debug, Clone)]
+pub struct DensityGrid {
+    size: usize,
+    values: Vec<f32>,
+}
+
+impl DensityGrid {
+    pub fn from_fn(size: usize, mut sample: impl FnMut(UVec3) -> f32) -> Self {
+        let samples = size as u32 + 1;
+        let mut values = Vec::with_capacity((samples * samples * samples) as usize);
+        for x in 0..samples {
+            for y in 0..samples {
+                for z in 0..samples {
+                    values.push(sample(UVec3::new(x, y, z)));
+                }
+            }
+        }
+        Self { size, values }
+    }
+
+    /// Samples `density` for the chunk at `offset`
+    pub fn from_density(density: &dyn DensityFunction, offset: Vec3, size: usize) -> Self {
+        let samples = SampleGrid::new(density, offset, size + 1, 1, false);
+        Self {
+            size,
+            values: samples.values,
+        }
+    }
+
+    /// Returns `None` if there isn't one value per sample
+    pub fn from_values(size: usize, values: Vec<f32>) -> Option<Self> {
+        let samples = size + 1;
+        (values.len() == samples * samples * samples).then_some(Self { size, values })
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    pub fn values(&self) -> &[f32] {
+        &self.values
+    }
+
+    pub fn get(&self, point: UVec3) -> f32 {
+        self.values[self.index(point)]
+    }
+
+    pub fn get_mut(&mut self, point: UVec3) -> &mut f32 {
+        let index = self.index(point);
+        &mut self.values[index]
+    }
+
+    fn index(&self, point: UVec3) -> usize {
+        let samples = self.size as u32 + 1;
+        ((point.x * samples + point.y) * samples + point.z) as usize
+    }
+}
+
+/// Density of every sample a chunk is meshed from, sampled at once. Samples are
+/// `stride` units apart and indexed from `first` on every axis, which is -1 if
+/// the grid includes a one sample wide apron around the chunk
+#[derive(Debug, Clone)]
+pub struct SampleGrid {
+    first: i32,
+    samples: i32,
+    values: Vec<f32>,
+}
+
+impl SampleGrid {
+    /// Samples `density` at `samples` points on every axis of the chunk at `offset`
+    pub fn new(
+        density: &dyn DensityFunction,
+        offset: Vec3,
+        samples: usize,
+        stride: usize,
+        apron: bool,
+    ) -> Self {
+        let first = -(apron as i32);
+        let samples = samples as i32 - 2 * first;
+        let stride = stride as i32;
+        let range = first..first + samples;
+        let mut points = Vec::with_capacity((samples * samples * samples) as usize);
+        for x in range.clone() {
+            for y in range.clone() {
+                for z in range.clone() {
+                    points.push((IVec3::new(x, y, z) * stride).as_vec3() + offset);
+                }
+            }
+        }
+        let mut values = vec![0.0; points.len()];
+        density.sample_batch(&points, &mut values);
+        Self {
+            first,
+            samples,
+            values,
+        }
+    }
+
+    /// Index of the first sample on every axis
+    pub fn first(&self) -> i32 {
+        self.first
+    }
+
+    /// Number of samples on every axis, including the apron
+    pub fn samples(&self) -> i32 {
+        self.samples
+    }
+
+    /// Number of samples on every axis of the chunk itself
+    pub fn chunk_samples(&self) -> i32 {
+        self.samples + 2 * self.first
+    }
+
+    pub fn get(&self, point: IVec3) -> f32 {
+        let point = point - IVec3::splat(self.first);
+        self.values[((point.x * self.samples + point.y) * self.samples + point.z) as usize]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::preset::WorldPreset;
+    use super::*;
+    use std::sync::Arc;
+
+    #[test]
+    fn batch_matches_single_samples() {
+        let preset =
+            WorldPreset::from_ron(include_bytes!("../../../assets/worlds/default.preset.ron"))
+                .unwrap();
+        let density = preset.build_density(Arc::new(preset.build_biomes()));
+        // Far enough apart to cross biomes
+        for offset in [Vec3::ZERO, Vec3::new(-800.0, 40.0, 1300.0)] {
+            let grid = SampleGrid::new(density.as_ref(), offset, 9, 4, true);
+            for x in -1..=9 {
+                for y in -1..=9 {
+                    for z in -1..=9 {
+                        let sample = IVec3::new(x, y, z);
+                        let expected = density.sample((sample * 4).as_vec3() + offset);
+                        assert_eq!(grid.get(sample).to_bits(), expected.to_bits(), "{sample}");
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,162 @@
+use super::chunks::{self, ChunkSettings, LoadedChunks};
+use super::physics::{ChunkColliders, ColliderTask};
+use super::queue::{GenerationMetrics, GenerationQueue};
+use super::WorldInfo;
+use crate::player::Controlled;
+use bevy::{prelude::*, utils::Instant};
+use tracing::info;
+
+const BAR_WIDTH: f32 = 400.0;
+const BAR_HEIGHT: f32 = 16.0;
+
+/// The player is held in place while loading, until the chunks around it exist
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, States)]
+pub enum GameState {
+    #[default]
+    Loading,
+    Playing,
+}
+
+/// Chunks around the player that are done, out of the ones needed to start playing
+#[derive(Debug, Resource, Reflect)]
+pub struct LoadingProgress {
+    pub ready: usize,
+    pub total: usize,
+    #[reflect(ignore)]
+    start: Instant,
+}
+
+impl Default for LoadingProgress {
+    fn default() -> Self {
+        Self {
+            ready: 0,
+            total: 0,
+            start: Instant::now(),
+        }
+    }
+}
+
+impl LoadingProgress {
+    pub fn fraction(&self) -> f32 {
+        if self.total == 0 {
+            return 0.0;
+        }
+        self.ready as f32 / self.total as f32
+    }
+}
+
+#[derive(Debug, Component)]
+pub struct LoadingScreen;
+
+#[derive(Debug, Component)]
+pub struct ProgressBar;
+
+pub fn spawn_loading_screen(mut commands: Commands, mut progress: ResMut<LoadingProgress>) {
+    *progress = LoadingProgress::default();
+    commands
+        .spawn((
+            LoadingScreen,
+            NodeBundle {
+                style: Style {
+                    size: Size::all(Val::Percent(100.0)),
+                    align_items: AlignItems::Center,
+                    justify_content: JustifyContent::Center,
+                    ..default()
+                },
+                background_color: Color::rgb(0.02, 0.0, 0.1).into(),
+                ..default()
+            },
+        ))
+        .with_children(|parent| {
+            parent
+                .spawn(NodeBundle {
+                    style: Style {
+                        size: Size::new(Val::Px(BAR_WIDTH), Val::Px(BAR_HEIGHT)),
+                        ..default()
+                    },
+                    background_color: Color::rgb(0.1, 0.1, 0.3).into(),
+                    ..default()
+                })
+                .with_children(|parent| {
+                    parent.spawn((
+                        ProgressBar,
+                        NodeBundle {
+                            style: Style {
+                                size: Size::new(Val::Percent(0.0), Val::Percent(100.0)),
+                                ..default()
+                            },
+                            background_color: Color::rgb(0.3, 0.8, 0.9).into(),
+                            ..default()
+                        },
+                    ));
+                });
+        });
+}
+
+/// Counts the chunks around the player that have their mesh and collider,
+/// and starts playing once all of them do
+#[allow(clippy::too_many_arguments)]
+pub fn update_loading_progress(
+    info: Res<WorldInfo>,
+    settings: Res<ChunkSettings>,
+    loaded: Res<LoadedChunks>,
+    queue: Res<GenerationQueue>,
+    colliders: Res<ChunkColliders>,
+    mut progress: ResMut<LoadingProgress>,
+    mut next_state: ResMut<NextState<GameState>>,
+    player: Query<&GlobalTransform, With<Controlled>>,
+    collider_tasks: Query<(), With<ColliderTask>>,
+    mut bars: Query<&mut Style, With<ProgressBar>>,
+) {
+    let Ok(transform) = player.get_single() else { return };
+    let center = info.chunk_coord(transform.translation());
+    // Every chunk the player can touch right away
+    let radius = settings.physics_radius.max(0);
+    let (mut ready, mut total) = (0, 0);
+    for x in -radius..=radius {
+        for y in -radius..=radius {
+            for z in -radius..=radius {
+                let relative = IVec3::new(x, y, z);
+                if !chunks::in_view(relative, radius) {
+                    continue;
+                }
+                total += 1;
+                let coord = center + relative;
+                let Some((entity, _)) = loaded.get(coord) else { continue };
+                if !queue.contains(coord, entity)
+                    && colliders.contains(coord)
+                    && !collider_tasks.contains(entity)
+                {
+                    ready += 1;
+                }
+            }
+        }
+    }
+    progress.ready = ready;
+    progress.total = total;
+    for mut style in bars.iter_mut() {
+        style.size.width = Val::Percent(progress.fraction() * 100.0);
+    }
+    if ready == total {
+        next_state.set(GameState::Playing);
+    }
+}
+
+pub fn despawn_loading_screen(
+    mut commands: Commands,
+    progress: Res<LoadingProgress>,
+    metrics: Res<GenerationMetrics>,
+    screens: Query<Entity, With<LoadingScreen>>,
+) {
+    for entity in screens.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+    info!(
+        chunks = progress.total,
+        generated = metrics.completed,
+        queued = metrics.queued,
+        average_task_ms = metrics.average_task_ms,
+        "World ready to play in {:.3}ms",
+        progress.start.elapsed().as_secs_f32() * 1000.0
+    );
+}
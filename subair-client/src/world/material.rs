@@ -0,0 +1,127 @@
+use super::density::{DensityFunction, NoiseLayer, NoiseSettings};
+use bevy::{
+    pbr::{MaterialPipeline, MaterialPipelineKey, MeshPipelineKey},
+    prelude::*,
+    reflect::TypeUuid,
+    render::{
+        mesh::{MeshVertexAttribute, MeshVertexBufferLayout},
+        render_resource::{
+            AsBindGroup, RenderPipelineDescriptor, ShaderRef, SpecializedMeshPipelineError,
+            VertexFormat,
+        },
+    },
+};
+
+const TERRAIN_SHADER: &str = "shaders/terrain.wgsl";
+
+// Ids of custom attributes should be random to avoid collisions
+pub const ATTRIBUTE_MATERIAL_ID: MeshVertexAttribute =
+    MeshVertexAttribute::new("TerrainMaterialId", 2_918_437_551, VertexFormat::Uint32);
+
+// Normals pointing less upwards than this are too steep for anything to settle on
+const ROCK_MIN_UP: f32 = 0.7;
+// Sediment collects below this height, shifted by the layer noise
+const SEDIMENT_HEIGHT: f32 = -48.0;
+const SEDIMENT_HEIGHT_VARIATION: f32 = 32.0;
+// Layer noise above this value is sediment regardless of the height
+const SEDIMENT_PATCH_THRESHOLD: f32 = 0.45;
+// Keeps the layer noise from lining up with terrain noise using the same settings
+const LAYER_SEED_OFFSET: u64 = 0x1A7E_5EED;
+
+/// Surface type of the terrain, stored per vertex in `ATTRIBUTE_MATERIAL_ID`.
+/// The values have to match the ones in the terrain shader
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u32)]
+pub enum TerrainLayer {
+    Rock = 0,
+    Sand = 1,
+    Sediment = 2,
+}
+
+impl TerrainLayer {
+    fn color(self) -> Vec3 {
+        match self {
+            TerrainLayer::Rock => Vec3::new(0.42, 0.4, 0.38),
+            TerrainLayer::Sand => Vec3::new(0.86, 0.78, 0.6),
+            TerrainLayer::Sediment => Vec3::new(0.3, 0.24, 0.18),
+        }
+    }
+}
+
+/// Picks the terrain layer of every vertex from its height, slope and a
+/// secondary noise field, returning vertex colors and material ids
+pub fn layer_attributes(
+    vertices: &[[f32; 3]],
+    normals: &[[f32; 3]],
+    offset: Vec3,
+    seed: u64,
+) -> (Vec<[f32; 4]>, Vec<u32>) {
+    let noise = NoiseLayer::new(
+        seed.wrapping_add(LAYER_SEED_OFFSET),
+        NoiseSettings {
+            octaves: 2,
+            frequency: 0.03,
+            ..default()
+        },
+        1.0,
+    );
+    vertices
+        .iter()
+        .zip(normals)
+        .map(|(vertex, normal)| {
+            let position = Vec3::from(*vertex) + offset;
+            let value = noise.sample(position);
+            let layer = if normal[1] < ROCK_MIN_UP {
+                TerrainLayer::Rock
+            } else if value > SEDIMENT_PATCH_THRESHOLD
+                || position.y < SEDIMENT_HEIGHT + value * SEDIMENT_HEIGHT_VARIATION
+            {
+                TerrainLayer::Sediment
+            } else {
+                TerrainLayer::Sand
+            };
+            // Small variation so large areas of one layer don't look flat
+            let color = layer.color() * (1.0 + value * 0.15);
+            (color.extend(1.0).to_array(), layer as u32)
+        })
+        .unzip()
+}
+
+/// Lit terrain material blending the layers stored in the mesh, tinted by the biome
+#[derive(AsBindGroup, Debug, Clone, TypeUuid)]
+#[uuid = "3c0f7d52-6a9e-4f0b-9d61-0f5e2b8c4a17"]
+pub struct TerrainMaterial {
+    #[uniform(0)]
+    pub tint: Color,
+}
+
+impl Material for TerrainMaterial {
+    fn vertex_shader() -> ShaderRef {
+        TERRAIN_SHADER.into()
+    }
+
+    fn fragment_shader() -> ShaderRef {
+        TERRAIN_SHADER.into()
+    }
+
+    fn specialize(
+        _pipeline: &MaterialPipeline<Self>,
+        descriptor: &mut RenderPipelineDescriptor,
+        layout: &MeshVertexBufferLayout,
+        key: MaterialPipelineKey<Self>,
+    ) -> Result<(), SpecializedMeshPipelineError> {
+        // Shadows and prepasses use the default shaders, which only read the position and normal
+        let prepass = MeshPipelineKey::DEPTH_PREPASS | MeshPipelineKey::NORMAL_PREPASS;
+        if key.mesh_key.intersects(prepass) {
+            return Ok(());
+        }
+        let vertex_layout = layout.get_layout(&[
+            Mesh::ATTRIBUTE_POSITION.at_shader_location(0),
+            Mesh::ATTRIBUTE_NORMAL.at_shader_location(1),
+            Mesh::ATTRIBUTE_COLOR.at_shader_location(2),
+            ATTRIBUTE_MATERIAL_ID.at_shader_location(3),
+        ])?;
+        descriptor.vertex.buffers = vec![vertex_layout];
+        Ok(())
+    }
+}
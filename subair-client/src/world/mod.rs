@@ -1,104 +1,163 @@
-mod generate;
-mod kd_tree;
+mod biome;
+mod chunks;
+pub mod density;
+mod edit;
+pub mod generate;
+pub mod grid;
+mod loading;
 mod marching_cubes_tables;
-mod normals;
+mod material;
+pub mod normals;
+mod perlin;
+mod physics;
+pub mod preset;
+mod queue;
+mod save;
 
-use bevy::{
-    prelude::*,
-    tasks::{AsyncComputeTaskPool, Task},
-    utils::Instant,
-};
-use bevy_rapier3d::prelude::*;
+use bevy::{prelude::*, tasks::Task};
+use biome::{BiomeFog, BiomeMaterials, WorldBiomes};
+use chunks::{Chunk, ChunkSettings, LoadedChunks};
+use density::DensityFunction;
+use edit::TerrainEdits;
 use futures_lite::future::{block_on, poll_once};
+use generate::{GeneratedChunk, NormalMode, Welding};
+use loading::LoadingProgress;
+use material::TerrainMaterial;
+use physics::ChunkColliders;
+use preset::{WorldPreset, WorldPresetLoader};
+use queue::{GenerationMetrics, GenerationQueue};
+use save::{Autosave, PendingPlayer, SaveSlot};
+use std::sync::Arc;
+
+pub use chunks::MAX_LOD;
+pub use edit::{EditMode, EditShape, TerrainEdit};
+pub use loading::GameState;
 
 pub struct WorldPlugin;
 
 impl Plugin for WorldPlugin {
     fn build(&self, app: &mut App) {
         app.register_type::<WorldInfo>()
-            .register_type::<WorldTimingData>()
-            .insert_resource(WorldInfo {
-                seed: 23478235784239483,
-            })
-            .add_startup_system(schedule_world_gen)
-            .add_startup_system(setup)
-            .add_system(collect_world_mesh);
+            .register_type::<GenerationMetrics>()
+            .register_type::<LoadingProgress>()
+            .register_type::<ChunkSettings>()
+            .register_type::<Chunk>()
+            .register_type::<NormalMode>()
+            .register_type::<Welding>()
+            .register_type::<BiomeFog>()
+            .add_plugin(MaterialPlugin::<TerrainMaterial>::default())
+            .add_state::<GameState>()
+            .add_asset::<WorldPreset>()
+            .init_asset_loader::<WorldPresetLoader>()
+            .init_resource::<LoadedChunks>()
+            .init_resource::<TerrainEdits>()
+            .init_resource::<ChunkColliders>()
+            .init_resource::<GenerationQueue>()
+            .init_resource::<GenerationMetrics>()
+            .init_resource::<LoadingProgress>()
+            .init_resource::<SaveSlot>()
+            .init_resource::<Autosave>()
+            .add_event::<TerrainEdit>()
+            .add_startup_system(save::load_world)
+            .add_system(collect_world_mesh.run_if(resource_exists::<WorldBiomes>()))
+            // Despawning must happen after the collected meshes are inserted
+            .add_system(preset::apply_world_preset.after(collect_world_mesh))
+            .add_system(
+                edit::apply_terrain_edits
+                    .after(collect_world_mesh)
+                    .run_if(resource_exists::<WorldDensity>()),
+            )
+            .add_system(
+                chunks::update_loaded_chunks
+                    .after(edit::apply_terrain_edits)
+                    .run_if(resource_exists::<WorldDensity>()),
+            )
+            // Colliders have to be inserted before their chunk can be despawned
+            .add_system(
+                physics::collect_chunk_colliders
+                    .before(preset::apply_world_preset)
+                    .before(chunks::update_loaded_chunks)
+                    .run_if(resource_exists::<WorldInfo>()),
+            )
+            .add_system(
+                queue::dispatch_generation
+                    .after(chunks::update_loaded_chunks)
+                    .run_if(resource_exists::<WorldDensity>()),
+            )
+            .add_system(
+                physics::update_chunk_colliders
+                    .after(chunks::update_loaded_chunks)
+                    .run_if(resource_exists::<WorldDensity>()),
+            )
+            .add_system(loading::spawn_loading_screen.in_schedule(OnEnter(GameState::Loading)))
+            .add_system(loading::despawn_loading_screen.in_schedule(OnExit(GameState::Loading)))
+            .add_system(
+                loading::update_loading_progress
+                    .after(physics::update_chunk_colliders)
+                    .run_if(in_state(GameState::Loading))
+                    .run_if(resource_exists::<WorldInfo>()),
+            )
+            .add_system(biome::blend_biome_fog.run_if(resource_exists::<WorldBiomes>()))
+            .add_system(save::restore_player.run_if(resource_exists::<PendingPlayer>()))
+            .add_system(save::autosave.run_if(resource_exists::<WorldInfo>()));
     }
 }
 
 #[derive(Debug, Reflect, Resource)]
 pub struct WorldInfo {
     seed: u64,
+    normal_mode: NormalMode,
+    welding: Welding,
+    chunk_size: usize,
 }
 
-#[derive(Debug, Resource)]
-struct WorldMaterial(Handle<StandardMaterial>);
-
-#[derive(Component)]
-pub struct WorldMeshTask(Task<(Mesh, Collider, Vec3)>);
+impl WorldInfo {
+    pub fn chunk_coord(&self, position: Vec3) -> IVec3 {
+        (position / self.chunk_size as f32).floor().as_ivec3()
+    }
 
-#[derive(Debug, Resource, Reflect)]
-pub struct WorldTimingData {
-    start: Instant,
-    chunks_left: u32,
+    pub fn chunk_offset(&self, coord: IVec3) -> Vec3 {
+        coord.as_vec3() * self.chunk_size as f32
+    }
 }
 
-fn setup(mut commands: Commands, mut materials: ResMut<Assets<StandardMaterial>>) {
-    let handle = materials.add(StandardMaterial {
-        base_color: Color::ORANGE_RED,
-        ..default()
-    });
-    commands.insert_resource(WorldMaterial(handle));
-}
+#[derive(Resource, Clone)]
+pub struct WorldDensity(pub Arc<dyn DensityFunction>);
 
-fn schedule_world_gen(info: Res<WorldInfo>, mut commands: Commands) {
-    let pool = AsyncComputeTaskPool::get();
-    let seed = info.seed;
-    let start = Instant::now();
-    for x in 0..10 {
-        for y in 0..10 {
-            for z in 0..10 {
-                let offset = Vec3::new(x as f32 * 31.0, y as f32 * 31.0, z as f32 * 31.0);
-                let task = pool.spawn(async move { generate::generate_world(seed, offset, 32) });
-                commands.spawn(WorldMeshTask(task));
-            }
-        }
-    }
-    commands.insert_resource(WorldTimingData {
-        start,
-        chunks_left: 10 * 10 * 10,
-    });
-}
+#[derive(Component)]
+pub struct WorldMeshTask(Task<GeneratedChunk>);
 
+#[allow(clippy::too_many_arguments)]
 fn collect_world_mesh(
     mut commands: Commands,
     mut tasks: Query<(Entity, &mut WorldMeshTask)>,
     mut meshes: ResMut<Assets<Mesh>>,
-    material: Res<WorldMaterial>,
-    timing_data: Option<ResMut<WorldTimingData>>,
+    info: Res<WorldInfo>,
+    biomes: Res<WorldBiomes>,
+    materials: Res<BiomeMaterials>,
+    mut queue: ResMut<GenerationQueue>,
+    mut metrics: ResMut<GenerationMetrics>,
 ) {
-    let Some(mut timing_data) = timing_data else { return };
     for (entity, mut task) in tasks.iter_mut() {
-        if let Some((mesh, collider, offset)) = block_on(poll_once(&mut task.0)) {
+        // Only finished tasks are polled, as there can be many running ones
+        if !task.0.is_finished() {
+            continue;
+        }
+        if let Some(chunk) = block_on(poll_once(&mut task.0)) {
+            // Chunks take the material of the biome at their center
+            let center = chunk.offset + Vec3::splat(info.chunk_size as f32 / 2.0);
+            let material = materials.0[biomes.0.dominant(center)].clone();
             commands
                 .entity(entity)
-                .insert(PbrBundle {
-                    material: material.0.clone(),
-                    mesh: meshes.add(mesh),
-                    transform: Transform::from_translation(offset),
+                .insert(MaterialMeshBundle {
+                    material,
+                    mesh: meshes.add(chunk.mesh),
+                    transform: Transform::from_translation(chunk.offset),
                     ..default()
                 })
-                .insert((RigidBody::Fixed, collider))
                 .remove::<WorldMeshTask>();
-            if timing_data.chunks_left <= 1 {
-                info!(
-                    "World generation done in {:.3}ms",
-                    timing_data.start.elapsed().as_secs_f32() * 1000.0
-                );
-                commands.remove_resource::<WorldTimingData>();
-            } else {
-                timing_data.chunks_left -= 1;
-            }
+            queue.finish(entity);
+            metrics.record_task(chunk.stats.duration.as_secs_f32() * 1000.0);
         }
     }
 }
@@ -0,0 +1,211 @@
+//! Perlin noise of bracket-noise, evaluated for many points at once. Every
+//! step is done for a whole lane of points before the next one, so the
+//! arithmetic vectorises, and the results match `FastNoise::get_noise3d` exactly
+
+use super::density::NoiseSettings;
+use bevy::prelude::*;
+use bracket_noise::prelude::{FractalType, NoiseType};
+use bracket_random::prelude::RandomNumberGenerator;
+
+/// Points evaluated together
+const LANES: usize = 8;
+
+const GRAD_X: [f32; 12] = [1., -1., 1., -1., 1., -1., 1., -1., 0., 0., 0., 0.];
+const GRAD_Y: [f32; 12] = [1., 1., -1., -1., 0., 0., 0., 0., 1., -1., 1., -1.];
+const GRAD_Z: [f32; 12] = [0., 0., 0., 0., 1., 1., -1., -1., 1., 1., -1., -1.];
+
+type Lane = [f32; LANES];
+
+pub struct BatchedPerlin {
+    perm: [u8; 512],
+    perm12: [u8; 512],
+    settings: NoiseSettings,
+    fractal: bool,
+    fractal_bounding: f32,
+}
+
+impl BatchedPerlin {
+    /// `None` for noise types other than Perlin
+    pub fn new(seed: u64, settings: NoiseSettings) -> Option<Self> {
+        let fractal = match settings.noise_type {
+            NoiseType::Perlin => false,
+            NoiseType::PerlinFractal => true,
+            _ => return None,
+        };
+        // The permutation `FastNoise::set_seed` shuffles
+        let mut rng = RandomNumberGenerator::seeded(seed);
+        let mut perm = [0; 512];
+        let mut perm12 = [0; 512];
+        for (i, value) in perm.iter_mut().take(256).enumerate() {
+            *value = i as u8;
+        }
+        for j in 0..256 {
+            let k = (rng.next_u64() % (256 - j as u64)) as usize + j;
+            let l = perm[j];
+            perm[j] = perm[k];
+            perm[j + 256] = perm[k];
+            perm[k] = l;
+            perm12[j] = perm[j] % 12;
+            perm12[j + 256] = perm[j] % 12;
+        }
+        let mut amplitude = settings.gain;
+        let mut sum = 1.0;
+        for _ in 0..settings.octaves {
+            sum += amplitude;
+            amplitude *= settings.gain;
+        }
+        Some(Self {
+            perm,
+            perm12,
+            settings,
+            fractal,
+            fractal_bounding: 1.0 / sum,
+        })
+    }
+
+    /// Noise at every point, multiplied by `amplitude`
+    pub fn sample_batch(&self, points: &[Vec3], amplitude: f32, out: &mut [f32]) {
+        for (points, out) in points.chunks(LANES).zip(out.chunks_mut(LANES)) {
+            let mut x = [0.0; LANES];
+            let mut y = [0.0; LANES];
+            let mut z = [0.0; LANES];
+            for (i, point) in points.iter().enumerate() {
+                x[i] = point.x * self.settings.frequency;
+                y[i] = point.y * self.settings.frequency;
+                z[i] = point.z * self.settings.frequency;
+            }
+            let values = if self.fractal {
+                self.fractal_lane(x, y, z)
+            } else {
+                self.perlin_lane(0, &x, &y, &z)
+            };
+            for (out, value) in out.iter_mut().zip(values) {
+                *out = value * amplitude;
+            }
+        }
+    }
+
+    fn fractal_lane(&self, mut x: Lane, mut y: Lane, mut z: Lane) -> Lane {
+        let fractal_type = self.settings.fractal_type;
+        let octave = |value: f32| match fractal_type {
+            FractalType::FBM => value,
+            FractalType::Billow => value.abs() * 2.0 - 1.0,
+            FractalType::RigidMulti => 1.0 - value.abs(),
+        };
+        let mut sum = self.perlin_lane(self.perm[0], &x, &y, &z).map(octave);
+        let mut amplitude = 1.0;
+        for i in 1..self.settings.octaves.max(1) as usize {
+            for lane in [&mut x, &mut y, &mut z] {
+                for value in lane.iter_mut() {
+                    *value *= self.settings.lacunarity;
+                }
+            }
+            amplitude *= self.settings.gain;
+            let values = self.perlin_lane(self.perm[i & 0xff], &x, &y, &z);
+            for (sum, value) in sum.iter_mut().zip(values) {
+                match fractal_type {
+                    FractalType::RigidMulti => *sum -= octave(value) * amplitude,
+                    _ => *sum += octave(value) * amplitude,
+                }
+            }
+        }
+        if fractal_type != FractalType::RigidMulti {
+            for sum in sum.iter_mut() {
+                *sum *= self.fractal_bounding;
+            }
+        }
+        sum
+    }
+
+    fn perlin_lane(&self, offset: u8, x: &Lane, y: &Lane, z: &Lane) -> Lane {
+        let floor = |lane: &Lane| lane.map(|v| if v >= 0.0 { v as i32 } else { v as i32 - 1 });
+        let (x0, y0, z0) = (floor(x), floor(y), floor(z));
+        let mut d0 = [[0.0; LANES]; 3];
+        for i in 0..LANES {
+            d0[0][i] = x[i] - x0[i] as f32;
+            d0[1][i] = y[i] - y0[i] as f32;
+            d0[2][i] = z[i] - z0[i] as f32;
+        }
+        let d1 = d0.map(|lane| lane.map(|d| d - 1.0));
+        let s = d0.map(|lane| lane.map(|t| t * t * t * (t * (t * 6. - 15.) + 10.)));
+
+        let mut result = [0.0; LANES];
+        for i in 0..LANES {
+            let gradient = |cx: i32, cy: i32, cz: i32, xd: f32, yd: f32, zd: f32| {
+                let z_index = self.perm[((cz & 0xff) as usize + offset as usize) & 0x1ff];
+                let y_index = self.perm[((cy & 0xff) as usize + z_index as usize) & 0x1ff];
+                let lut = self.perm12[((cx & 0xff) as usize + y_index as usize) & 0x1ff] as usize;
+                xd * GRAD_X[lut] + yd * GRAD_Y[lut] + zd * GRAD_Z[lut]
+            };
+            let (x0, y0, z0) = (x0[i], y0[i], z0[i]);
+            let (x1, y1, z1) = (x0 + 1, y0 + 1, z0 + 1);
+            let (xd0, yd0, zd0) = (d0[0][i], d0[1][i], d0[2][i]);
+            let (xd1, yd1, zd1) = (d1[0][i], d1[1][i], d1[2][i]);
+            let (xs, ys, zs) = (s[0][i], s[1][i], s[2][i]);
+            let xf00 = lerp(
+                gradient(x0, y0, z0, xd0, yd0, zd0),
+                gradient(x1, y0, z0, xd1, yd0, zd0),
+                xs,
+            );
+            let xf10 = lerp(
+                gradient(x0, y1, z0, xd0, yd1, zd0),
+                gradient(x1, y1, z0, xd1, yd1, zd0),
+                xs,
+            );
+            let xf01 = lerp(
+                gradient(x0, y0, z1, xd0, yd0, zd1),
+                gradient(x1, y0, z1, xd1, yd0, zd1),
+                xs,
+            );
+            let xf11 = lerp(
+                gradient(x0, y1, z1, xd0, yd1, zd1),
+                gradient(x1, y1, z1, xd1, yd1, zd1),
+                xs,
+            );
+            result[i] = lerp(lerp(xf00, xf10, ys), lerp(xf01, xf11, ys), zs);
+        }
+        result
+    }
+}
+
+fn lerp(a: f32, b: f32, t: f32) -> f32 {
+    a + t * (b - a)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_fast_noise() {
+        let points: Vec<_> = (0..500)
+            .map(|i| {
+                let i = i as f32;
+                Vec3::new(i * 1.37 - 300.0, (i * 0.73).sin() * 50.0, i * -0.41 + 17.0)
+            })
+            .collect();
+        let fractal_types = [
+            FractalType::FBM,
+            FractalType::Billow,
+            FractalType::RigidMulti,
+        ];
+        for noise_type in [NoiseType::Perlin, NoiseType::PerlinFractal] {
+            for fractal_type in fractal_types {
+                let settings = NoiseSettings {
+                    noise_type,
+                    fractal_type,
+                    octaves: 4,
+                    ..default()
+                };
+                let noise = settings.build(42);
+                let batched = BatchedPerlin::new(42, settings).unwrap();
+                let mut out = vec![0.0; points.len()];
+                batched.sample_batch(&points, 2.0, &mut out);
+                for (point, value) in points.iter().zip(out) {
+                    let expected = noise.get_noise3d(point.x, point.y, point.z) * 2.0;
+                    assert_eq!(value.to_bits(), expected.to_bits(), "{point}");
+                }
+            }
+        }
+    }
+}
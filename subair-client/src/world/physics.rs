@@ -0,0 +1,148 @@
+use super::chunks::{self, Chunk, ChunkSettings, LoadedChunks};
+use super::edit::{Neighbourhood, TerrainEdits};
+use super::{generate, WorldDensity, WorldInfo};
+use bevy::{
+    prelude::*,
+    tasks::{AsyncComputeTaskPool, Task},
+    utils::HashMap,
+};
+use bevy_rapier3d::prelude::*;
+use futures_lite::future::{block_on, poll_once};
+use tracing::debug;
+
+// Colliders are only removed this many chunks outside the physics radius, so
+// moving back and forth across a chunk border doesn't rebuild them
+const REMOVE_MARGIN: i32 = 1;
+
+#[derive(Component)]
+pub struct ColliderTask(Task<Option<Collider>>);
+
+/// Chunks around moving bodies, which have a collider or are building one
+#[derive(Debug, Resource, Default)]
+pub struct ChunkColliders {
+    chunks: HashMap<IVec3, Entity>,
+}
+
+impl ChunkColliders {
+    pub fn contains(&self, coord: IVec3) -> bool {
+        self.chunks.contains_key(&coord)
+    }
+}
+
+/// Starts building the collider of a chunk, `None` if the chunk is empty or
+/// solid and has nothing to collide with
+pub fn collider_task(
+    info: &WorldInfo,
+    density: &WorldDensity,
+    settings: &ChunkSettings,
+    coord: IVec3,
+    edits: Neighbourhood,
+) -> Option<ColliderTask> {
+    if !chunks::has_surface(info, density, coord, &edits) {
+        return None;
+    }
+    let (density, offset, size, lod) = (
+        density.0.clone(),
+        info.chunk_offset(coord),
+        info.chunk_size,
+        settings.collider_lod,
+    );
+    let task = AsyncComputeTaskPool::get()
+        .spawn(async move { generate::generate_collider(density, offset, size, lod, edits) });
+    Some(ColliderTask(task))
+}
+
+/// Builds colliders for the chunks around every moving body and removes them
+/// from the chunks left behind
+#[allow(clippy::too_many_arguments)]
+pub fn update_chunk_colliders(
+    mut commands: Commands,
+    info: Res<WorldInfo>,
+    density: Res<WorldDensity>,
+    settings: Res<ChunkSettings>,
+    edits: Res<TerrainEdits>,
+    loaded: Res<LoadedChunks>,
+    mut colliders: ResMut<ChunkColliders>,
+    bodies: Query<(&RigidBody, &GlobalTransform), Without<Chunk>>,
+) {
+    // Fixed bodies never run into the terrain
+    let centers: Vec<_> = bodies
+        .iter()
+        .filter(|(body, _)| **body != RigidBody::Fixed)
+        .map(|(_, transform)| info.chunk_coord(transform.translation()))
+        .collect();
+    let radius = settings.physics_radius;
+    let near = |coord: IVec3, radius: i32| {
+        centers
+            .iter()
+            .any(|center| chunks::in_view(coord - *center, radius))
+    };
+
+    // Changed settings can change the collider LOD, so every collider is built again
+    let rebuild = settings.is_changed();
+    let mut removed = 0;
+    colliders.chunks.retain(|coord, entity| {
+        let loaded_entity = loaded.get(*coord).map(|(entity, _)| entity);
+        if !rebuild && loaded_entity == Some(*entity) && near(*coord, radius + REMOVE_MARGIN) {
+            return true;
+        }
+        if let Some(mut entity) = commands.get_entity(*entity) {
+            entity.remove::<(RigidBody, Collider, ColliderTask)>();
+        }
+        removed += 1;
+        false
+    });
+
+    let mut added = 0;
+    for center in &centers {
+        for x in -radius..=radius {
+            for y in -radius..=radius {
+                for z in -radius..=radius {
+                    let relative = IVec3::new(x, y, z);
+                    let coord = *center + relative;
+                    if !chunks::in_view(relative, radius) || colliders.contains(coord) {
+                        continue;
+                    }
+                    // Chunks that aren't loaded yet are picked up once they are
+                    let Some((entity, _)) = loaded.get(coord) else { continue };
+                    let neighbourhood = edits.neighbourhood(coord);
+                    if let Some(task) =
+                        collider_task(&info, &density, &settings, coord, neighbourhood)
+                    {
+                        commands.entity(entity).insert(task);
+                    }
+                    colliders.chunks.insert(coord, entity);
+                    added += 1;
+                }
+            }
+        }
+    }
+    if added > 0 || removed > 0 {
+        debug!(added, removed, "Updated chunk colliders");
+    }
+}
+
+pub fn collect_chunk_colliders(
+    mut commands: Commands,
+    info: Res<WorldInfo>,
+    mut tasks: Query<(Entity, &Chunk, &mut ColliderTask, Option<&Transform>)>,
+) {
+    for (entity, chunk, mut task, transform) in tasks.iter_mut() {
+        let Some(collider) = block_on(poll_once(&mut task.0)) else { continue };
+        let mut entity = commands.entity(entity);
+        entity.remove::<ColliderTask>();
+        let Some(collider) = collider else {
+            // Edits can remove the whole surface of a chunk
+            entity.remove::<(RigidBody, Collider)>();
+            continue;
+        };
+        entity.insert((RigidBody::Fixed, collider));
+        // The collider can be done before the mesh, which brings the transform
+        if transform.is_none() {
+            let offset = info.chunk_offset(chunk.0);
+            entity.insert(TransformBundle::from_transform(
+                Transform::from_translation(offset),
+            ));
+        }
+    }
+}
@@ -0,0 +1,332 @@
+use super::biome::{BiomeDefinition, BiomeDensity, BiomeMap, BiomeMaterials, WorldBiomes};
+use super::chunks::{ChunkSettings, LoadedChunks, MAX_LOD};
+use super::density::{self, BoxedDensity, NoiseSettings};
+use super::edit::TerrainEdits;
+use super::generate::{NormalMode, Welding};
+use super::loading::GameState;
+use super::material::TerrainMaterial;
+use super::queue::GenerationQueue;
+use super::save::SavedSeed;
+use super::{WorldDensity, WorldInfo};
+use bevy::{
+    asset::{AssetLoader, LoadContext, LoadedAsset},
+    prelude::*,
+    reflect::TypeUuid,
+    utils::BoxedFuture,
+};
+use serde::Deserialize;
+use std::sync::Arc;
+use tracing::{info, warn};
+
+pub const DEFAULT_PRESET: &str = "worlds/default.preset.ron";
+
+/// Every parameter needed to generate a world
+#[derive(Debug, Clone, Deserialize, TypeUuid)]
+#[uuid = "6f1b6a2e-93c4-4bd5-8f0e-4a3f8f3a5c21"]
+pub struct WorldPreset {
+    pub seed: u64,
+    /// Density of the surface, everything denser is solid
+    #[serde(default)]
+    pub floor: f32,
+    #[serde(default)]
+    pub normal_mode: NormalMode,
+    #[serde(default)]
+    pub welding: Welding,
+    pub chunk_size: usize,
+    pub view_radius: i32,
+    pub lod_distances: Vec<i32>,
+    /// Radius in chunks around moving bodies in which chunks get colliders
+    #[serde(default = "default_physics_radius")]
+    pub physics_radius: i32,
+    /// LOD colliders are built at, higher values decimate them
+    #[serde(default)]
+    pub collider_lod: u32,
+    /// Generation tasks that may run at the same time, the rest wait in a queue
+    #[serde(default = "default_max_generation_tasks")]
+    pub max_generation_tasks: usize,
+    pub density: DensityNode,
+    /// Noise over chunk coordinates that picks the biome
+    #[serde(default = "default_biome_noise")]
+    pub biome_noise: NoiseSettings,
+    #[serde(default = "default_biome_sharpness")]
+    pub biome_sharpness: f32,
+    /// Ordered along the biome noise, neighbours blend into each other
+    pub biomes: Vec<BiomeDefinition>,
+}
+
+fn default_biome_noise() -> NoiseSettings {
+    NoiseSettings {
+        frequency: 0.15,
+        ..default()
+    }
+}
+
+fn default_biome_sharpness() -> f32 {
+    4.0
+}
+
+fn default_physics_radius() -> i32 {
+    2
+}
+
+fn default_max_generation_tasks() -> usize {
+    8
+}
+
+/// Serializable description of a `DensityFunction`
+#[derive(Debug, Clone, Deserialize)]
+pub enum DensityNode {
+    Noise {
+        #[serde(default)]
+        settings: NoiseSettings,
+        amplitude: f32,
+        // Layers with the same settings would be identical without it
+        #[serde(default)]
+        seed_offset: u64,
+    },
+    Sphere {
+        center: Vec3,
+        radius: f32,
+    },
+    Cuboid {
+        center: Vec3,
+        half_extents: Vec3,
+    },
+    HeightBias {
+        height: f32,
+        gradient: f32,
+    },
+    Constant(f32),
+    DomainWarp {
+        #[serde(default)]
+        settings: NoiseSettings,
+        amplitude: f32,
+        #[serde(default)]
+        seed_offset: u64,
+        inner: Box<DensityNode>,
+    },
+    Add(Vec<DensityNode>),
+    Min(Box<DensityNode>, Box<DensityNode>),
+    Max(Box<DensityNode>, Box<DensityNode>),
+    Negate(Box<DensityNode>),
+    SmoothUnion {
+        a: Box<DensityNode>,
+        b: Box<DensityNode>,
+        smoothness: f32,
+    },
+}
+
+impl DensityNode {
+    pub fn build(&self, seed: u64) -> BoxedDensity {
+        match self {
+            DensityNode::Noise {
+                settings,
+                amplitude,
+                seed_offset,
+            } => Box::new(density::NoiseLayer::new(
+                seed.wrapping_add(*seed_offset),
+                *settings,
+                *amplitude,
+            )),
+            DensityNode::Sphere { center, radius } => Box::new(density::Sphere {
+                center: *center,
+                radius: *radius,
+            }),
+            DensityNode::Cuboid {
+                center,
+                half_extents,
+            } => Box::new(density::Cuboid {
+                center: *center,
+                half_extents: *half_extents,
+            }),
+            DensityNode::HeightBias { height, gradient } => Box::new(density::HeightBias {
+                height: *height,
+                gradient: *gradient,
+            }),
+            DensityNode::Constant(value) => Box::new(density::Constant(*value)),
+            DensityNode::DomainWarp {
+                settings,
+                amplitude,
+                seed_offset,
+                inner,
+            } => Box::new(density::DomainWarp::new(
+                seed.wrapping_add(*seed_offset),
+                *settings,
+                *amplitude,
+                inner.build(seed),
+            )),
+            DensityNode::Add(nodes) => Box::new(density::Add(
+                nodes.iter().map(|node| node.build(seed)).collect(),
+            )),
+            DensityNode::Min(a, b) => Box::new(density::Min(a.build(seed), b.build(seed))),
+            DensityNode::Max(a, b) => Box::new(density::Max(a.build(seed), b.build(seed))),
+            DensityNode::Negate(node) => Box::new(density::Negate(node.build(seed))),
+            DensityNode::SmoothUnion { a, b, smoothness } => Box::new(density::SmoothUnion {
+                a: a.build(seed),
+                b: b.build(seed),
+                smoothness: *smoothness,
+            }),
+        }
+    }
+}
+
+impl WorldPreset {
+    /// Parses and validates a preset
+    pub fn from_ron(bytes: &[u8]) -> Result<WorldPreset, bevy::asset::Error> {
+        let preset: WorldPreset = ron::de::from_bytes(bytes)?;
+        let stride = 1 << MAX_LOD;
+        if preset.chunk_size == 0 || !preset.chunk_size.is_multiple_of(stride) {
+            return Err(bevy::asset::Error::msg(format!(
+                "Chunk size {} is not a multiple of {stride}",
+                preset.chunk_size
+            )));
+        }
+        if preset.collider_lod > MAX_LOD {
+            return Err(bevy::asset::Error::msg(format!(
+                "Collider LOD {} is coarser than the last LOD {MAX_LOD}",
+                preset.collider_lod
+            )));
+        }
+        // Chunks only load within the view radius, so the loading screen
+        // would wait for physics chunks that never exist
+        if preset.physics_radius > preset.view_radius {
+            return Err(bevy::asset::Error::msg(format!(
+                "Physics radius {} is larger than the view radius {}",
+                preset.physics_radius, preset.view_radius
+            )));
+        }
+        if preset.max_generation_tasks == 0 {
+            return Err(bevy::asset::Error::msg(
+                "At least one generation task has to run at a time",
+            ));
+        }
+        if preset.biomes.is_empty() {
+            return Err(bevy::asset::Error::msg("A world needs at least one biome"));
+        }
+        Ok(preset)
+    }
+
+    pub fn build_biomes(&self) -> BiomeMap {
+        BiomeMap::new(
+            self.seed,
+            self.biome_noise,
+            self.biome_sharpness,
+            self.chunk_size,
+            &self.biomes,
+        )
+    }
+
+    pub fn build_density(&self, biomes: Arc<BiomeMap>) -> BoxedDensity {
+        let mut density = self.density.build(self.seed);
+        if self.floor != 0.0 {
+            // The mesher expects the surface at zero
+            density = Box::new(density::Add(vec![
+                density,
+                Box::new(density::Constant(-self.floor)),
+            ]));
+        }
+        Box::new(BiomeDensity {
+            base: density,
+            biomes,
+        })
+    }
+}
+
+#[derive(Default)]
+pub struct WorldPresetLoader;
+
+impl AssetLoader for WorldPresetLoader {
+    fn load<'a>(
+        &'a self,
+        bytes: &'a [u8],
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<(), bevy::asset::Error>> {
+        Box::pin(async move {
+            let preset = WorldPreset::from_ron(bytes)?;
+            load_context.set_default_asset(LoadedAsset::new(preset));
+            Ok(())
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["preset.ron"]
+    }
+}
+
+#[derive(Debug, Resource)]
+pub struct WorldPresetHandle(pub Handle<WorldPreset>);
+
+/// Replaces the world whenever the preset is loaded or changed on disk
+#[allow(clippy::too_many_arguments)]
+pub fn apply_world_preset(
+    mut commands: Commands,
+    mut events: EventReader<AssetEvent<WorldPreset>>,
+    presets: Res<Assets<WorldPreset>>,
+    handle: Res<WorldPresetHandle>,
+    mut loaded: ResMut<LoadedChunks>,
+    mut edits: ResMut<TerrainEdits>,
+    mut queue: ResMut<GenerationQueue>,
+    state: Res<State<GameState>>,
+    mut next_state: ResMut<NextState<GameState>>,
+    mut materials: ResMut<Assets<TerrainMaterial>>,
+    saved_seed: Option<Res<SavedSeed>>,
+) {
+    let (mut created, mut modified) = (false, false);
+    for event in events.iter() {
+        match event {
+            AssetEvent::Created { handle: changed } => created |= *changed == handle.0,
+            AssetEvent::Modified { handle: changed } => modified |= *changed == handle.0,
+            AssetEvent::Removed { .. } => {}
+        }
+    }
+    if !created && !modified {
+        return;
+    }
+    let Some(preset) = presets.get(&handle.0) else { return };
+    let mut preset = preset.clone();
+    match saved_seed {
+        // The save no longer matches the changed preset
+        Some(_) if modified => commands.remove_resource::<SavedSeed>(),
+        Some(seed) => preset.seed = seed.0,
+        None => {}
+    }
+
+    commands.insert_resource(WorldInfo {
+        seed: preset.seed,
+        normal_mode: preset.normal_mode,
+        welding: preset.welding,
+        chunk_size: preset.chunk_size,
+    });
+    let biomes = Arc::new(preset.build_biomes());
+    let biome_materials = biomes
+        .biomes()
+        .iter()
+        .map(|biome| materials.add(TerrainMaterial { tint: biome.color }))
+        .collect();
+    commands.insert_resource(BiomeMaterials(biome_materials));
+    commands.insert_resource(WorldDensity(Arc::from(
+        preset.build_density(biomes.clone()),
+    )));
+    commands.insert_resource(WorldBiomes(biomes));
+    commands.insert_resource(ChunkSettings {
+        view_radius: preset.view_radius,
+        lod_distances: preset.lod_distances.clone(),
+        physics_radius: preset.physics_radius,
+        collider_lod: preset.collider_lod,
+        max_generation_tasks: preset.max_generation_tasks,
+    });
+    if modified {
+        // Edits were made to the old density
+        edits.clear();
+    }
+    let dropped = edits.retain_size(preset.chunk_size);
+    if dropped > 0 {
+        warn!(dropped, "Dropped edits made with a different chunk size");
+    }
+    loaded.unload_all(&mut commands, &mut queue);
+    // The player waits for the new terrain around it
+    if state.0 == GameState::Playing {
+        next_state.set(GameState::Loading);
+    }
+    info!(seed = preset.seed, "Applied world preset");
+}
@@ -0,0 +1,209 @@
+use super::chunks::{self, ChunkSettings};
+use super::edit::TerrainEdits;
+use super::{WorldDensity, WorldInfo, WorldMeshTask};
+use crate::player::Controlled;
+use bevy::{
+    prelude::*,
+    utils::{HashMap, HashSet, Instant},
+};
+use tracing::{debug, info};
+
+// Seconds over which the throughput is averaged
+const THROUGHPUT_SMOOTHING: f32 = 1.0;
+// Weight of the latest task in the average task time
+const TASK_TIME_SMOOTHING: f32 = 0.1;
+
+/// Chunks waiting for a generation task and the chunks whose task is running
+#[derive(Debug, Resource, Default)]
+pub struct GenerationQueue {
+    // Entity and LOD of every waiting chunk
+    pending: HashMap<IVec3, (Entity, u32)>,
+    running: HashSet<Entity>,
+    completed: u64,
+    cancelled: u64,
+}
+
+impl GenerationQueue {
+    /// Queues the chunk at `lod`, replacing an earlier request. A running
+    /// task is cancelled, as its mesh would be outdated
+    pub fn request(&mut self, commands: &mut Commands, coord: IVec3, entity: Entity, lod: u32) {
+        if self.running.remove(&entity) {
+            // Dropping the task cancels it, the old mesh stays until the new one is done
+            commands.entity(entity).remove::<WorldMeshTask>();
+            self.cancelled += 1;
+        }
+        if self.pending.insert(coord, (entity, lod)).is_some() {
+            self.cancelled += 1;
+        }
+    }
+
+    /// Forgets the chunk, which is about to be despawned along with its task
+    pub fn cancel(&mut self, coord: IVec3, entity: Entity) {
+        if self.pending.remove(&coord).is_some() {
+            self.cancelled += 1;
+        }
+        if self.running.remove(&entity) {
+            self.cancelled += 1;
+        }
+    }
+
+    /// Forgets every chunk, which are about to be despawned
+    pub fn clear(&mut self) {
+        self.cancelled += (self.pending.len() + self.running.len()) as u64;
+        self.pending.clear();
+        self.running.clear();
+    }
+
+    /// Called once the task of the chunk is done
+    pub fn finish(&mut self, entity: Entity) {
+        if self.running.remove(&entity) {
+            self.completed += 1;
+        }
+    }
+
+    /// Whether the chunk waits for a task or its task is running
+    pub fn contains(&self, coord: IVec3, entity: Entity) -> bool {
+        self.pending.contains_key(&coord) || self.running.contains(&entity)
+    }
+
+    pub fn is_idle(&self) -> bool {
+        self.pending.is_empty() && self.running.is_empty()
+    }
+}
+
+/// Progress of the generation queue, for the inspector
+#[derive(Debug, Resource, Reflect, Default)]
+pub struct GenerationMetrics {
+    /// Chunks waiting for a task
+    pub queued: usize,
+    /// Tasks that are running
+    pub in_flight: usize,
+    /// Chunks generated since the start
+    pub completed: u64,
+    /// Requests dropped before their chunk was generated
+    pub cancelled: u64,
+    /// Chunks generated per second
+    pub throughput: f32,
+    /// Average time a task takes to generate its chunk
+    pub average_task_ms: f32,
+    // Start of the current burst of generation and the chunks completed
+    // before it, `None` while the queue is idle
+    #[reflect(ignore)]
+    busy_since: Option<(Instant, u64)>,
+    #[reflect(ignore)]
+    last_completed: u64,
+}
+
+impl GenerationMetrics {
+    pub fn record_task(&mut self, milliseconds: f32) {
+        self.average_task_ms += (milliseconds - self.average_task_ms) * TASK_TIME_SMOOTHING;
+    }
+}
+
+/// Lower values are generated first. Chunks behind the player count as up to
+/// twice as far away as chunks in front of it
+fn priority(relative: Vec3, forward: Vec3) -> f32 {
+    let facing = relative.normalize_or_zero().dot(forward);
+    relative.length() * (1.5 - 0.5 * facing)
+}
+
+/// Starts tasks for the most important queued chunks, up to the limit of tasks in flight
+#[allow(clippy::too_many_arguments)]
+pub fn dispatch_generation(
+    mut commands: Commands,
+    info: Res<WorldInfo>,
+    density: Res<WorldDensity>,
+    settings: Res<ChunkSettings>,
+    edits: Res<TerrainEdits>,
+    mut queue: ResMut<GenerationQueue>,
+    mut metrics: ResMut<GenerationMetrics>,
+    time: Res<Time>,
+    player: Query<&GlobalTransform, With<Controlled>>,
+) {
+    let capacity = settings
+        .max_generation_tasks
+        .saturating_sub(queue.running.len());
+    if capacity > 0 && !queue.pending.is_empty() {
+        // Without a player every direction is just as important
+        let (position, forward) = player
+            .get_single()
+            .map_or((Vec3::ZERO, Vec3::ZERO), |transform| {
+                (transform.translation(), transform.forward())
+            });
+        let half_chunk = Vec3::splat(info.chunk_size as f32 / 2.0);
+        let mut order: Vec<_> = queue
+            .pending
+            .keys()
+            .map(|coord| {
+                let center = info.chunk_offset(*coord) + half_chunk;
+                (priority(center - position, forward), *coord)
+            })
+            .collect();
+        if order.len() > capacity {
+            order.select_nth_unstable_by(capacity, |a, b| a.0.total_cmp(&b.0));
+            order.truncate(capacity);
+        }
+        // The task pool starts tasks in the order they are spawned
+        order.sort_unstable_by(|a, b| a.0.total_cmp(&b.0));
+        for (_, coord) in order {
+            let (entity, lod) = queue.pending.remove(&coord).unwrap();
+            let task =
+                chunks::generation_task(&info, &density, coord, lod, edits.neighbourhood(coord));
+            commands.entity(entity).insert(task);
+            queue.running.insert(entity);
+        }
+        debug!(
+            queued = queue.pending.len(),
+            in_flight = queue.running.len(),
+            "Dispatched generation tasks"
+        );
+    }
+
+    metrics.queued = queue.pending.len();
+    metrics.in_flight = queue.running.len();
+    metrics.cancelled = queue.cancelled;
+    let finished = queue.completed - metrics.last_completed;
+    metrics.completed = queue.completed;
+    metrics.last_completed = queue.completed;
+    let delta = time.delta_seconds();
+    if delta > 0.0 {
+        let t = 1.0 - (-delta / THROUGHPUT_SMOOTHING).exp();
+        metrics.throughput += (finished as f32 / delta - metrics.throughput) * t;
+    }
+
+    match metrics.busy_since {
+        None if !queue.is_idle() => metrics.busy_since = Some((Instant::now(), queue.completed)),
+        Some((start, completed)) if queue.is_idle() => {
+            let seconds = start.elapsed().as_secs_f32();
+            let chunks = queue.completed - completed;
+            info!(
+                chunks,
+                average_task_ms = metrics.average_task_ms,
+                chunks_per_second = chunks as f32 / seconds,
+                cancelled = queue.cancelled,
+                "World generation done in {:.3}ms",
+                seconds * 1000.0
+            );
+            metrics.busy_since = None;
+        }
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn chunks_in_front_come_first() {
+        let forward = Vec3::NEG_Z;
+        let ahead = priority(Vec3::new(0.0, 0.0, -64.0), forward);
+        let beside = priority(Vec3::new(64.0, 0.0, 0.0), forward);
+        let behind = priority(Vec3::new(0.0, 0.0, 64.0), forward);
+        assert!(ahead < beside && beside < behind);
+        // Distance still matters more than direction
+        assert!(priority(Vec3::new(0.0, 0.0, 16.0), forward) < ahead);
+        // The chunk around the player comes first
+        assert_eq!(priority(Vec3::ZERO, forward), 0.0);
+    }
+}
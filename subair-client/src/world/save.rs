@@ -0,0 +1,475 @@
+use super::edit::TerrainEdits;
+use super::grid::DensityGrid;
+use super::preset::{WorldPresetHandle, DEFAULT_PRESET};
+use super::WorldInfo;
+use crate::player::Controlled;
+use bevy::{
+    prelude::*,
+    tasks::{IoTaskPool, Task},
+    utils::HashMap,
+};
+use flate2::{read::ZlibDecoder, write::ZlibEncoder, Compression};
+use futures_lite::future::{block_on, poll_once};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Instant;
+use tracing::{info, warn};
+
+/// Saves with a different version can't be loaded
+pub const SAVE_VERSION: u32 = 2;
+pub const SAVE_DIRECTORY: &str = "saves/default";
+const HEADER_FILE: &str = "world.ron";
+const REGION_DIRECTORY: &str = "regions";
+const REGION_EXTENSION: &str = "region";
+const REGION_MAGIC: &[u8; 4] = b"SBRG";
+/// Chunks per axis stored in one region file
+const REGION_SIZE: i32 = 8;
+const AUTOSAVE_INTERVAL: f32 = 60.0;
+
+/// Everything about a world that isn't generated from the preset
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SaveHeader {
+    pub version: u32,
+    pub seed: u64,
+    /// Asset path of the world preset
+    pub preset: String,
+    pub player: Option<PlayerState>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PlayerState {
+    pub transform: Transform,
+    pub controlled: Controlled,
+}
+
+#[derive(Debug, Clone)]
+pub struct SaveData {
+    pub header: SaveHeader,
+    /// Edited density grids by chunk coordinate
+    pub chunks: Vec<(IVec3, Arc<DensityGrid>)>,
+}
+
+#[derive(Debug, Resource)]
+pub struct SaveSlot {
+    pub directory: PathBuf,
+}
+
+impl Default for SaveSlot {
+    fn default() -> Self {
+        Self {
+            directory: SAVE_DIRECTORY.into(),
+        }
+    }
+}
+
+/// Seed of the loaded save, used instead of the one in the preset
+#[derive(Debug, Resource)]
+pub struct SavedSeed(pub u64);
+
+/// Player state from the loaded save, applied once the player is spawned
+#[derive(Debug, Resource)]
+pub struct PendingPlayer(PlayerState);
+
+#[derive(Resource)]
+pub struct Autosave {
+    timer: Timer,
+    task: Option<Task<io::Result<()>>>,
+}
+
+impl Default for Autosave {
+    fn default() -> Self {
+        Self {
+            timer: Timer::from_seconds(AUTOSAVE_INTERVAL, TimerMode::Repeating),
+            task: None,
+        }
+    }
+}
+
+fn invalid_data(message: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.into())
+}
+
+fn region_coord(chunk: IVec3) -> IVec3 {
+    IVec3::new(
+        chunk.x.div_euclid(REGION_SIZE),
+        chunk.y.div_euclid(REGION_SIZE),
+        chunk.z.div_euclid(REGION_SIZE),
+    )
+}
+
+fn region_path(directory: &Path, region: IVec3) -> PathBuf {
+    directory.join(REGION_DIRECTORY).join(format!(
+        "{}.{}.{}.{REGION_EXTENSION}",
+        region.x, region.y, region.z
+    ))
+}
+
+/// Region coordinate in the name of a region file
+fn path_region(path: &Path) -> Option<IVec3> {
+    let stem = path.file_stem()?.to_str()?;
+    let components = stem
+        .split('.')
+        .map(|component| component.parse().ok())
+        .collect::<Option<Vec<i32>>>()?;
+    match components[..] {
+        [x, y, z] => Some(IVec3::new(x, y, z)),
+        _ => None,
+    }
+}
+
+// Replaces the file only once it is completely written
+fn write_atomic(path: &Path, bytes: &[u8]) -> io::Result<()> {
+    let temporary = path.with_extension("tmp");
+    fs::write(&temporary, bytes)?;
+    fs::rename(temporary, path)
+}
+
+/// Region files start with a magic number and the save version, followed by
+/// the compressed coordinates and samples of every chunk
+pub fn encode_region(chunks: &[(IVec3, Arc<DensityGrid>)]) -> io::Result<Vec<u8>> {
+    let mut bytes = REGION_MAGIC.to_vec();
+    bytes.extend_from_slice(&SAVE_VERSION.to_le_bytes());
+    let mut encoder = ZlibEncoder::new(bytes, Compression::default());
+    encoder.write_all(&(chunks.len() as u32).to_le_bytes())?;
+    for (coord, grid) in chunks {
+        for component in coord.to_array() {
+            encoder.write_all(&component.to_le_bytes())?;
+        }
+        encoder.write_all(&(grid.size() as u32).to_le_bytes())?;
+        for value in grid.values() {
+            encoder.write_all(&value.to_le_bytes())?;
+        }
+    }
+    encoder.finish()
+}
+
+/// Chunks of the region file of `region`, which may only contain chunks of that region
+pub fn decode_region(region: IVec3, bytes: &[u8]) -> io::Result<Vec<(IVec3, Arc<DensityGrid>)>> {
+    if bytes.len() < 8 || &bytes[..4] != REGION_MAGIC {
+        return Err(invalid_data("Not a region file"));
+    }
+    let version = u32::from_le_bytes(bytes[4..8].try_into().unwrap());
+    if version != SAVE_VERSION {
+        return Err(invalid_data(format!(
+            "Region version {version} is not supported, expected {SAVE_VERSION}"
+        )));
+    }
+    let mut decoder = ZlibDecoder::new(&bytes[8..]);
+    let mut read_u32 = || -> io::Result<u32> {
+        let mut buffer = [0; 4];
+        decoder.read_exact(&mut buffer)?;
+        Ok(u32::from_le_bytes(buffer))
+    };
+    let count = read_u32()?;
+    if count > REGION_SIZE.pow(3) as u32 {
+        return Err(invalid_data(format!(
+            "Region with {count} chunks, it only fits {}",
+            REGION_SIZE.pow(3)
+        )));
+    }
+    let mut chunks = Vec::with_capacity(count as usize);
+    for _ in 0..count {
+        let coord = IVec3::new(read_u32()? as i32, read_u32()? as i32, read_u32()? as i32);
+        if region_coord(coord) != region {
+            return Err(invalid_data(format!(
+                "Chunk {coord} is outside of region {region}"
+            )));
+        }
+        let size = read_u32()? as usize;
+        // Sizes that fit are only limited by the samples actually in the file
+        let samples = (size + 1)
+            .checked_pow(3)
+            .ok_or_else(|| invalid_data(format!("Chunk size {size} is too large")))?;
+        let values = (0..samples)
+            .map(|_| read_u32().map(f32::from_bits))
+            .collect::<io::Result<_>>()?;
+        let grid = DensityGrid::from_values(size, values)
+            .ok_or_else(|| invalid_data("Wrong number of samples"))?;
+        chunks.push((coord, Arc::new(grid)));
+    }
+    Ok(chunks)
+}
+
+pub fn write_save(directory: &Path, data: &SaveData) -> io::Result<()> {
+    let regions_directory = directory.join(REGION_DIRECTORY);
+    fs::create_dir_all(&regions_directory)?;
+
+    let mut regions: HashMap<IVec3, Vec<(IVec3, Arc<DensityGrid>)>> = HashMap::default();
+    for (coord, grid) in &data.chunks {
+        regions
+            .entry(region_coord(*coord))
+            .or_default()
+            .push((*coord, grid.clone()));
+    }
+    let mut written = vec![];
+    for (region, chunks) in &regions {
+        let path = region_path(directory, *region);
+        write_atomic(&path, &encode_region(chunks)?)?;
+        written.push(path);
+    }
+    // Regions whose edits were discarded, e.g. because the preset changed
+    for entry in fs::read_dir(&regions_directory)? {
+        let path = entry?.path();
+        if !written.contains(&path) {
+            fs::remove_file(path)?;
+        }
+    }
+
+    let header = ron::ser::to_string_pretty(&data.header, Default::default())
+        .map_err(|error| invalid_data(error.to_string()))?;
+    write_atomic(&directory.join(HEADER_FILE), header.as_bytes())
+}
+
+/// Returns `None` if there is no save in `directory`
+pub fn read_save(directory: &Path) -> io::Result<Option<SaveData>> {
+    let header = match fs::read_to_string(directory.join(HEADER_FILE)) {
+        Ok(header) => header,
+        Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(error) => return Err(error),
+    };
+    let header: SaveHeader =
+        ron::from_str(&header).map_err(|error| invalid_data(error.to_string()))?;
+    if header.version != SAVE_VERSION {
+        return Err(invalid_data(format!(
+            "Save version {} is not supported, expected {SAVE_VERSION}",
+            header.version
+        )));
+    }
+
+    let mut chunks = vec![];
+    let regions_directory = directory.join(REGION_DIRECTORY);
+    if regions_directory.is_dir() {
+        for entry in fs::read_dir(regions_directory)? {
+            let path = entry?.path();
+            if path.extension().is_some_and(|e| e == REGION_EXTENSION) {
+                let region = path_region(&path)
+                    .ok_or_else(|| invalid_data(format!("Not a region file {}", path.display())))?;
+                chunks.extend(decode_region(region, &fs::read(path)?)?);
+            }
+        }
+    }
+    Ok(Some(SaveData { header, chunks }))
+}
+
+/// Loads the save of the slot, or the default preset if there is none
+pub fn load_world(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    slot: Res<SaveSlot>,
+    mut edits: ResMut<TerrainEdits>,
+) {
+    let start = Instant::now();
+    let data = match read_save(&slot.directory) {
+        Ok(data) => data,
+        Err(error) => {
+            warn!(%error, directory = ?slot.directory, "Failed to load save, starting a new world");
+            None
+        }
+    };
+    let Some(data) = data else {
+        commands.insert_resource(WorldPresetHandle(asset_server.load(DEFAULT_PRESET)));
+        return;
+    };
+
+    let num_chunks = data.chunks.len();
+    for (coord, grid) in data.chunks {
+        edits.insert(coord, grid);
+    }
+    commands.insert_resource(WorldPresetHandle(asset_server.load(&data.header.preset)));
+    commands.insert_resource(SavedSeed(data.header.seed));
+    if let Some(player) = data.header.player {
+        commands.insert_resource(PendingPlayer(player));
+    }
+    info!(
+        num_chunks,
+        "Loaded save in {:.3}ms",
+        start.elapsed().as_secs_f32() * 1000.0
+    );
+}
+
+pub fn restore_player(
+    mut commands: Commands,
+    pending: Res<PendingPlayer>,
+    mut player: Query<(&mut Transform, &mut Controlled)>,
+) {
+    let Ok((mut transform, mut controlled)) = player.get_single_mut() else { return };
+    *transform = pending.0.transform;
+    *controlled = pending.0.controlled.clone();
+    commands.remove_resource::<PendingPlayer>();
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn autosave(
+    mut autosave: ResMut<Autosave>,
+    time: Res<Time>,
+    slot: Res<SaveSlot>,
+    info: Res<WorldInfo>,
+    edits: Res<TerrainEdits>,
+    handle: Res<WorldPresetHandle>,
+    asset_server: Res<AssetServer>,
+    player: Query<(&Transform, &Controlled)>,
+) {
+    if let Some(task) = &mut autosave.task {
+        let Some(result) = block_on(poll_once(task)) else { return };
+        match result {
+            Ok(()) => info!("Saved world"),
+            Err(error) => warn!(%error, "Failed to save world"),
+        }
+        autosave.task = None;
+    }
+    if !autosave.timer.tick(time.delta()).just_finished() {
+        return;
+    }
+    let Some(preset) = asset_server.get_handle_path(&handle.0) else { return };
+
+    let data = SaveData {
+        header: SaveHeader {
+            version: SAVE_VERSION,
+            seed: info.seed,
+            preset: preset.path().to_string_lossy().into_owned(),
+            player: player
+                .get_single()
+                .ok()
+                .map(|(transform, controlled)| PlayerState {
+                    transform: *transform,
+                    controlled: controlled.clone(),
+                }),
+        },
+        // Grids are shared, writing them doesn't block further edits
+        chunks: edits
+            .iter()
+            .map(|(coord, grid)| (coord, grid.clone()))
+            .collect(),
+    };
+    let directory = slot.directory.clone();
+    autosave.task = Some(IoTaskPool::get().spawn(async move { write_save(&directory, &data) }));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_grid(size: usize, seed: f32) -> Arc<DensityGrid> {
+        Arc::new(DensityGrid::from_fn(size, |p| {
+            (p.x as f32 * 0.5 - p.y as f32 + p.z as f32 * seed).sin()
+        }))
+    }
+
+    fn test_data() -> SaveData {
+        SaveData {
+            header: SaveHeader {
+                version: SAVE_VERSION,
+                seed: 1234,
+                preset: DEFAULT_PRESET.to_string(),
+                player: Some(PlayerState {
+                    transform: Transform::from_xyz(1.0, -2.0, 3.5)
+                        .with_rotation(Quat::from_rotation_y(0.3)),
+                    controlled: Controlled::default(),
+                }),
+            },
+            chunks: vec![
+                (IVec3::new(0, 0, 0), test_grid(8, 0.1)),
+                (IVec3::new(-1, 3, 0), test_grid(8, 0.2)),
+                // Far enough away to end up in another region
+                (IVec3::new(20, -17, 9), test_grid(8, 0.3)),
+            ],
+        }
+    }
+
+    fn sorted(mut chunks: Vec<(IVec3, Arc<DensityGrid>)>) -> Vec<(IVec3, Vec<f32>)> {
+        chunks.sort_by_key(|(coord, _)| coord.to_array());
+        chunks
+            .into_iter()
+            .map(|(coord, grid)| (coord, grid.values().to_vec()))
+            .collect()
+    }
+
+    fn temporary_directory(name: &str) -> PathBuf {
+        let directory = std::env::temp_dir().join(format!("subair-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&directory);
+        directory
+    }
+
+    #[test]
+    fn region_round_trip() {
+        let chunks: Vec<_> = test_data()
+            .chunks
+            .into_iter()
+            .filter(|(coord, _)| region_coord(*coord) == IVec3::ZERO)
+            .collect();
+        let decoded = decode_region(IVec3::ZERO, &encode_region(&chunks).unwrap()).unwrap();
+        assert_eq!(sorted(decoded), sorted(chunks));
+    }
+
+    #[test]
+    fn region_rejects_other_versions() {
+        let mut bytes = encode_region(&test_data().chunks).unwrap();
+        bytes[4..8].copy_from_slice(&(SAVE_VERSION + 1).to_le_bytes());
+        assert!(decode_region(IVec3::ZERO, &bytes).is_err());
+        assert!(decode_region(IVec3::ZERO, b"nope").is_err());
+    }
+
+    #[test]
+    fn region_rejects_corrupt_chunks() {
+        let region = |fields: &[u32]| {
+            let mut bytes = REGION_MAGIC.to_vec();
+            bytes.extend_from_slice(&SAVE_VERSION.to_le_bytes());
+            let mut encoder = ZlibEncoder::new(bytes, Compression::default());
+            for field in fields {
+                encoder.write_all(&field.to_le_bytes()).unwrap();
+            }
+            encoder.finish().unwrap()
+        };
+        let decode = |fields: &[u32]| decode_region(IVec3::ZERO, &region(fields)).unwrap_err();
+        assert_eq!(decode(&[u32::MAX]).kind(), io::ErrorKind::InvalidData);
+        // One chunk with a huge size
+        assert_eq!(
+            decode(&[1, 0, 0, 0, u32::MAX]).kind(),
+            io::ErrorKind::InvalidData
+        );
+        // A chunk of another region
+        assert_eq!(
+            decode(&[1, REGION_SIZE as u32, 0, 0, 8]).kind(),
+            io::ErrorKind::InvalidData
+        );
+        // A plausible size without the samples
+        decode(&[1, 0, 0, 0, 8]);
+    }
+
+    #[test]
+    fn region_names_round_trip() {
+        let region = IVec3::new(-3, 0, 12);
+        let path = region_path(Path::new("save"), region);
+        assert_eq!(path_region(&path), Some(region));
+        assert_eq!(path_region(Path::new("save/regions/0.1.region")), None);
+    }
+
+    #[test]
+    fn save_round_trip() {
+        let directory = temporary_directory("round-trip");
+        let data = test_data();
+        write_save(&directory, &data).unwrap();
+        let loaded = read_save(&directory).unwrap().unwrap();
+        assert_eq!(loaded.header, data.header);
+        assert_eq!(sorted(loaded.chunks), sorted(data.chunks.clone()));
+
+        // Saving fewer chunks removes the stale regions
+        let data = SaveData {
+            chunks: data.chunks[..1].to_vec(),
+            ..data
+        };
+        write_save(&directory, &data).unwrap();
+        let loaded = read_save(&directory).unwrap().unwrap();
+        assert_eq!(sorted(loaded.chunks), sorted(data.chunks));
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn missing_save_is_none() {
+        let directory = temporary_directory("missing");
+        assert!(read_save(&directory).unwrap().is_none());
+    }
+}
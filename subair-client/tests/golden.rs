@@ -0,0 +1,193 @@
+//! Compares generated chunks against hashes of previously generated ones, so
+//! changes to the meshing or the noise can't silently change every world.
+//! Run with `UPDATE_GOLDEN=1` to accept intentional changes
+
+use bevy::prelude::*;
+use bevy::render::mesh::{Indices, VertexAttributeValues};
+use std::sync::Arc;
+use subair::world::density::{
+    Add, BoxedDensity, DensityFunction, HeightBias, NoiseLayer, NoiseSettings, Sphere,
+};
+use subair::world::generate::{generate_world, NormalMode, Welding};
+
+const GOLDEN: &str = include_str!("golden/terrain.txt");
+const CHUNK_SIZE: usize = 32;
+
+struct Case {
+    name: &'static str,
+    seed: u64,
+    offset: Vec3,
+    lod: u32,
+    normal_mode: NormalMode,
+    welding: Welding,
+    density: fn(u64) -> BoxedDensity,
+}
+
+const CASES: &[Case] = &[
+    Case {
+        name: "sphere",
+        seed: 0,
+        offset: Vec3::ZERO,
+        lod: 0,
+        normal_mode: NormalMode::Faces,
+        welding: Welding::EdgeIndex,
+        density: sphere,
+    },
+    Case {
+        name: "terrain",
+        seed: 1,
+        offset: Vec3::new(0.0, -32.0, 0.0),
+        lod: 0,
+        normal_mode: NormalMode::Faces,
+        welding: Welding::EdgeIndex,
+        density: terrain,
+    },
+    Case {
+        name: "terrain_kd",
+        seed: 1,
+        offset: Vec3::new(0.0, -32.0, 0.0),
+        lod: 0,
+        normal_mode: NormalMode::Faces,
+        welding: Welding::KdTree,
+        density: terrain,
+    },
+    Case {
+        name: "terrain_gradient",
+        seed: 7,
+        offset: Vec3::new(32.0, -32.0, -64.0),
+        lod: 0,
+        normal_mode: NormalMode::Gradient,
+        welding: Welding::EdgeIndex,
+        density: terrain,
+    },
+    Case {
+        name: "terrain_lod1",
+        seed: 42,
+        offset: Vec3::new(-96.0, -32.0, 32.0),
+        lod: 1,
+        normal_mode: NormalMode::Faces,
+        welding: Welding::EdgeIndex,
+        density: terrain,
+    },
+    Case {
+        name: "terrain_lod2",
+        seed: 42,
+        offset: Vec3::new(-96.0, -32.0, 32.0),
+        lod: 2,
+        normal_mode: NormalMode::Gradient,
+        welding: Welding::EdgeIndex,
+        density: terrain,
+    },
+];
+
+fn sphere(_seed: u64) -> BoxedDensity {
+    // Off the sample lattice, so no vertex lands exactly on a sample
+    Box::new(Sphere {
+        center: Vec3::new(16.3, 15.8, 16.1),
+        radius: 9.6,
+    })
+}
+
+fn terrain(seed: u64) -> BoxedDensity {
+    let settings = NoiseSettings {
+        octaves: 3,
+        ..default()
+    };
+    Box::new(Add(vec![
+        Box::new(NoiseLayer::new(seed, settings, 1.0)),
+        Box::new(HeightBias {
+            height: -16.0,
+            gradient: 0.05,
+        }),
+    ]))
+}
+
+/// 64 bit FNV-1a
+fn fnv1a(bytes: impl IntoIterator<Item = u8>) -> u64 {
+    let mut hash = 0xcbf2_9ce4_8422_2325u64;
+    for byte in bytes {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+    }
+    hash
+}
+
+/// One line of the golden file: name, counts and hashes of the mesh
+fn describe(case: &Case) -> String {
+    let density: Arc<dyn DensityFunction> = Arc::from((case.density)(case.seed));
+    let chunk = generate_world(
+        density,
+        case.seed,
+        case.normal_mode,
+        case.welding,
+        case.offset,
+        CHUNK_SIZE,
+        case.lod,
+        default(),
+    );
+    let Some(VertexAttributeValues::Float32x3(positions)) =
+        chunk.mesh.attribute(Mesh::ATTRIBUTE_POSITION)
+    else {
+        panic!("Missing positions")
+    };
+    let Some(VertexAttributeValues::Float32x3(normals)) =
+        chunk.mesh.attribute(Mesh::ATTRIBUTE_NORMAL)
+    else {
+        panic!("Missing normals")
+    };
+    let Some(Indices::U32(indices)) = chunk.mesh.indices() else {
+        panic!("Missing indices")
+    };
+    for (position, normal) in positions.iter().zip(normals) {
+        assert!(
+            position.iter().chain(normal).all(|v| v.is_finite()),
+            "{}: vertex {position:?} with normal {normal:?}",
+            case.name
+        );
+    }
+
+    let float_bytes = |values: &[[f32; 3]]| {
+        values
+            .iter()
+            .flatten()
+            .flat_map(|v| v.to_bits().to_le_bytes())
+            .collect::<Vec<_>>()
+    };
+    format!(
+        "{} {} {} {:016x} {:016x} {:016x}",
+        case.name,
+        positions.len(),
+        indices.len(),
+        fnv1a(float_bytes(positions)),
+        fnv1a(float_bytes(normals)),
+        fnv1a(indices.iter().flat_map(|i| i.to_le_bytes())),
+    )
+}
+
+#[test]
+fn terrain_matches_golden() {
+    let header = "# name vertices indices positions normals indices";
+    let actual: Vec<_> = CASES.iter().map(describe).collect();
+
+    if std::env::var_os("UPDATE_GOLDEN").is_some() {
+        let path = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/golden/terrain.txt");
+        let contents = format!("{header}\n{}\n", actual.join("\n"));
+        std::fs::write(path, contents).expect("Failed to write golden file");
+        return;
+    }
+    let expected: Vec<_> = GOLDEN
+        .lines()
+        .filter(|line| !line.starts_with('#'))
+        .collect();
+    assert_eq!(
+        expected.len(),
+        actual.len(),
+        "Cases changed, rerun with UPDATE_GOLDEN=1"
+    );
+    for (expected, actual) in expected.iter().zip(&actual) {
+        assert_eq!(
+            expected, actual,
+            "Generated terrain changed, rerun with UPDATE_GOLDEN=1 if that is intended"
+        );
+    }
+}